@@ -0,0 +1,34 @@
+const vscode = require("vscode");
+const { LanguageClient } = require("vscode-languageclient/node");
+
+let client;
+
+function loxPath() {
+  return vscode.workspace.getConfiguration("lox").get("path");
+}
+
+// `lox_rs lsp` is the language server and `lox_rs dap` the debug adapter,
+// both talk over stdio
+function activate(context) {
+  client = new LanguageClient(
+    "lox",
+    "Lox",
+    { command: loxPath(), args: ["lsp"] },
+    { documentSelector: [{ scheme: "file", language: "lox" }] }
+  );
+  client.start();
+
+  context.subscriptions.push(
+    vscode.debug.registerDebugAdapterDescriptorFactory("lox", {
+      createDebugAdapterDescriptor() {
+        return new vscode.DebugAdapterExecutable(loxPath(), ["dap"]);
+      },
+    })
+  );
+}
+
+function deactivate() {
+  return client ? client.stop() : undefined;
+}
+
+module.exports = { activate, deactivate };
@@ -1,10 +1,15 @@
 use crate::{
     lox::LoxValue,
-    token::{Literal, Token},
+    token::{Literal, Position, Token},
+};
+use serde::{de::Error, Deserialize, Serialize};
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    str::FromStr,
 };
-use std::fmt::{Debug, Display, Formatter, Result};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
 pub enum Expr {
     Binary(Box<Expr>, Token, Box<Expr>),
     Grouping(Box<Expr>),
@@ -14,20 +19,73 @@ pub enum Expr {
     Assign(Token, Box<Expr>),
     Logical(Box<Expr>, Token, Box<Expr>),
     Call(Box<Expr>, Token, Vec<Expr>),
+    /// `value.name`, only the builtin types have methods
+    Get(Box<Expr>, Token),
+    /// `value[index]`, with the `[` token
+    Index(Box<Expr>, Token, Box<Expr>),
+    /// `value[index] = new_value`, with the `[` token
+    SetIndex(Box<Expr>, Token, Box<Expr>, Box<Expr>),
+    /// `[1, 2, 3]`, with the `[` token
+    List(Token, Vec<Expr>),
+    /// `{"a": 1, "b": 2}`, with the `{` token and the keys and values
+    Map(Token, Vec<(Expr, Expr)>),
+    /// `start..end` or `start..=end`, with the operator token
+    Range(Box<Expr>, Token, Box<Expr>),
+    /// A `loop`, an `if` or a block used as an expression, its value is the value of the statement
+    Statement(Box<Stmt>),
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
-            Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => {
-                write!(f, "({} {} {})", left, operator, right)
+            Expr::Binary(left, operator, right)
+            | Expr::Logical(left, operator, right)
+            | Expr::Range(left, operator, right) => write!(f, "({left} {operator} {right})"),
+            Expr::Grouping(expression) => write!(f, "(group {expression})"),
+            Expr::Literal(literal) => write!(f, "{literal}"),
+            Expr::Unary(operator, right) => write!(f, "({operator} {right})"),
+            Expr::Variable(token) => write!(f, "{token}"),
+            Expr::Assign(token, value) => write!(f, "({token} = {value})"),
+            Expr::Call(callee, _paren, args) => write!(f, "{callee}({args:?})"),
+            Expr::Get(object, name) => write!(f, "{object}.{name}"),
+            Expr::Index(object, _bracket, index) => write!(f, "{object}[{index}]"),
+            Expr::SetIndex(object, _bracket, index, value) => {
+                write!(f, "({object}[{index}] = {value})")
+            }
+            Expr::List(_bracket, items) => {
+                let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Expr::Map(_brace, entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{key}: {value}"))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Expr::Statement(stmt) => write!(f, "{stmt}"),
+        }
+    }
+}
+
+impl Expr {
+    /// The position of the first token of the expression, literals don't have one
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Expr::Binary(left, operator, _)
+            | Expr::Logical(left, operator, _)
+            | Expr::Range(left, operator, _)
+            | Expr::Get(left, operator)
+            | Expr::Index(left, operator, _)
+            | Expr::SetIndex(left, operator, ..) => left.position().or(Some(operator.position)),
+            Expr::List(bracket, _) | Expr::Map(bracket, _) => Some(bracket.position),
+            Expr::Grouping(expression) => expression.position(),
+            Expr::Literal(_) => None,
+            Expr::Unary(token, _) | Expr::Variable(token) | Expr::Assign(token, _) => {
+                Some(token.position)
             }
-            Expr::Grouping(expression) => write!(f, "(group {})", expression),
-            Expr::Literal(literal) => write!(f, "{}", literal),
-            Expr::Unary(operator, right) => write!(f, "({} {})", operator, right),
-            Expr::Variable(token) => write!(f, "{}", token),
-            Expr::Assign(token, value) => write!(f, "({} = {})", token, value),
-            Expr::Call(callee, _paren, args) => write!(f, "{}({:?})", callee, args),
+            Expr::Call(callee, paren, _) => callee.position().or(Some(paren.position)),
+            Expr::Statement(stmt) => stmt.position(),
         }
     }
 }
@@ -41,32 +99,76 @@ impl Debug for Expr {
                 .field(operator)
                 .field(right)
                 .finish(),
+            Expr::Range(start, operator, end) => f
+                .debug_tuple("Range")
+                .field(start)
+                .field(operator)
+                .field(end)
+                .finish(),
             Expr::Grouping(expression) => f.debug_tuple("Grouping").field(expression).finish(),
-            Expr::Literal(literal) => write!(f, "{:?}", literal),
+            Expr::Literal(literal) => write!(f, "{literal:?}"),
             Expr::Unary(operator, right) => {
                 f.debug_tuple("Unary").field(operator).field(right).finish()
             }
-            Expr::Variable(token) => write!(f, "{:?}", token),
+            Expr::Variable(token) => write!(f, "{token:?}"),
             Expr::Assign(token, value) => {
                 f.debug_tuple("Assign").field(token).field(value).finish()
             }
             Expr::Call(callee, _paren, args) => {
                 f.debug_tuple("Call").field(callee).field(args).finish()
             }
+            Expr::Get(object, name) => f.debug_tuple("Get").field(object).field(name).finish(),
+            Expr::Index(object, _bracket, index) => {
+                f.debug_tuple("Index").field(object).field(index).finish()
+            }
+            Expr::SetIndex(object, _bracket, index, value) => f
+                .debug_tuple("SetIndex")
+                .field(object)
+                .field(index)
+                .field(value)
+                .finish(),
+            Expr::List(_bracket, items) => f.debug_tuple("List").field(items).finish(),
+            Expr::Map(_brace, entries) => f.debug_tuple("Map").field(entries).finish(),
+            Expr::Statement(stmt) => f.debug_tuple("Statement").field(stmt).finish(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
 pub enum Stmt {
     Expression(Expr),
-    Print(Expr),
+    /// Statements with a keyword keep its token for their position
+    Print(Token, Expr),
     Block(Vec<Stmt>),
     Let(Token, Option<Expr>),
-    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>),
-    Break(Token),
-    Continue(Token),
+    If(Token, Expr, Box<Stmt>, Option<Box<Stmt>>),
+    /// `for` and `loop` are desugared to a `While` with their keyword and their label,
+    /// the increment of a `for` runs after the body, even when it continues
+    While(Token, Option<Token>, Expr, Box<Stmt>, Option<Expr>),
+    /// `break` and `continue` target the innermost loop, or the loop with their label,
+    /// `break` can give a value to its loop
+    Break(Token, Option<Token>, Option<Expr>),
+    Continue(Token, Option<Token>),
+    /// `for item in iterable`, with its keyword, its label and its variable
+    ForIn(Token, Option<Token>, Token, Expr, Box<Stmt>),
+}
+
+impl Stmt {
+    /// The position of the first token of the statement
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Stmt::Expression(expression) => expression.position(),
+            Stmt::Block(statements) => statements.first().and_then(Stmt::position),
+            Stmt::Print(token, _)
+            | Stmt::Let(token, _)
+            | Stmt::If(token, ..)
+            | Stmt::While(token, ..)
+            | Stmt::ForIn(token, ..)
+            | Stmt::Break(token, ..)
+            | Stmt::Continue(token, _) => Some(token.position),
+        }
+    }
 }
 
 macro_rules! indent {
@@ -87,11 +189,11 @@ impl Stmt {
     #[allow(clippy::range_plus_one)]
     fn fmt(&self, f: &mut Formatter<'_>, depth: i32) -> Result {
         match self {
-            Stmt::Expression(expression) => write!(f, "{}", expression),
-            Stmt::Print(expression) => write!(f, "(print {})", expression),
+            Stmt::Expression(expression) => write!(f, "{expression}"),
+            Stmt::Print(_, expression) => write!(f, "(print {expression})"),
             Stmt::Let(name, initializer) => match initializer {
-                Some(value) => write!(f, "(let {} = {})", name, value),
-                None => write!(f, "(let {} = None)", name),
+                Some(value) => write!(f, "(let {name} = {value})"),
+                None => write!(f, "(let {name} = None)"),
             },
             Stmt::Block(statements) => match statements.len() {
                 0 => indent!(f, depth + 1, "(empty_block)"),
@@ -104,7 +206,7 @@ impl Stmt {
                     indent!(f, depth, "}}")
                 }
             },
-            Stmt::If(condition, then_branch, else_branch) => {
+            Stmt::If(_, condition, then_branch, else_branch) => {
                 indent!(f, depth, "(if {} ", condition)?;
                 write_body(f, then_branch, depth)?;
                 if let Some(else_branch) = else_branch {
@@ -113,20 +215,65 @@ impl Stmt {
                 }
                 write!(f, ")")
             }
-            Stmt::While(condition, body) => {
-                indent!(f, depth, "(while {} ", condition)?;
+            Stmt::While(_, label, condition, body, increment) => {
+                indent!(f, depth, "(while {}{} ", Labeled(label.as_ref()), condition)?;
+                write_body(f, body, depth)?;
+                if let Some(increment) = increment {
+                    indent!(f, depth + 1, "(increment {})", increment)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::ForIn(_, label, variable, iterable, body) => {
+                indent!(
+                    f,
+                    depth,
+                    "(for {}{} in {} ",
+                    Labeled(label.as_ref()),
+                    variable,
+                    iterable
+                )?;
                 write_body(f, body, depth)?;
                 write!(f, ")")
             }
-            Stmt::Break(_token) => write!(f, "(break)"),
-            Stmt::Continue(_token) => write!(f, "(continue)"),
+            Stmt::Break(_token, label, value) => {
+                write!(f, "(break{}", Target(label.as_ref()))?;
+                if let Some(value) = value {
+                    write!(f, " {value}")?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Continue(_token, label) => write!(f, "(continue{})", Target(label.as_ref())),
+        }
+    }
+}
+
+/// The label of a loop, before its condition
+struct Labeled<'t>(Option<&'t Token>);
+
+impl Display for Labeled<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.0 {
+            Some(label) => write!(f, "{label}: "),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The label of a `break` or a `continue`, after its keyword
+struct Target<'t>(Option<&'t Token>);
+
+impl Display for Target<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.0 {
+            Some(label) => write!(f, " {label}"),
+            None => Ok(()),
         }
     }
 }
 
 fn write_body(f: &mut Formatter<'_>, body: &Stmt, depth: i32) -> Result {
     match body {
-        Stmt::Block(_) | Stmt::If(_, _, _) | Stmt::While(_, _) => body.fmt(f, depth),
+        Stmt::Block(_) | Stmt::If(..) | Stmt::While(..) | Stmt::ForIn(..) => body.fmt(f, depth),
         _ => indent!(f, depth, "{}", body),
     }
 }
@@ -137,9 +284,89 @@ impl Display for Stmt {
     }
 }
 
+/// Version of the json schema, bump it once per release when the serialized ast changed,
+/// the changes of an unreleased version share its number
+pub const JSON_VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct JsonProgramRef<'a> {
+    version: u32,
+    statements: &'a [Stmt],
+}
+
+#[derive(Deserialize)]
+struct JsonProgram {
+    version: u32,
+    statements: Vec<Stmt>,
+}
+
+/// Serializes a program to `{ "version": 2, "statements": [...] }`
+///
+/// Every enum is serialized as `{ "kind": <variant>, "value": <fields> }`
+pub fn to_json(statements: &[Stmt]) -> String {
+    serde_json::to_string_pretty(&JsonProgramRef {
+        version: JSON_VERSION,
+        statements,
+    })
+    .expect("ast should always be serializable")
+}
+
+pub fn from_json(json: &str) -> serde_json::Result<Vec<Stmt>> {
+    let program: JsonProgram = serde_json::from_str(json)?;
+    if program.version == JSON_VERSION {
+        Ok(program.statements)
+    } else {
+        Err(serde_json::Error::custom(format!(
+            "Unsupported ast version {}, expected {}",
+            program.version, JSON_VERSION
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AstFormat {
+    Sexpr,
+    Debug,
+    Json,
+}
+
+impl AstFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            AstFormat::Sexpr | AstFormat::Debug => "ast.lox",
+            AstFormat::Json => "ast.json",
+        }
+    }
+
+    pub fn format(self, statements: &[Stmt]) -> String {
+        match self {
+            AstFormat::Sexpr => {
+                let statements: Vec<String> = statements.iter().map(ToString::to_string).collect();
+                statements.join("\n")
+            }
+            AstFormat::Debug => format!("{statements:#?}\n"),
+            AstFormat::Json => to_json(statements),
+        }
+    }
+}
+
+impl FromStr for AstFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sexpr" => Ok(AstFormat::Sexpr),
+            "debug" => Ok(AstFormat::Debug),
+            "json" => Ok(AstFormat::Json),
+            _ => Err(format!("Unknown ast format \"{s}\"")),
+        }
+    }
+}
+
+/// `Break` and `Continue` have the label of the loop they target, `Break` has its value
 pub enum StmtResult {
-    Break,
-    Continue,
+    Break(Option<String>, LoxValue),
+    Continue(Option<String>),
     Value(LoxValue),
 }
 
@@ -0,0 +1,61 @@
+// Compatibility with jlox, the reference implementation from the book.
+//
+// Compat mode only changes what can't be written in both dialects: `if` and `while`
+// take a parenthesised condition and any statement as body, runtime errors stop the
+// program and errors are reported with the jlox format and wording.
+
+/// The jlox wording of an error message, `Expected ';' after value` is `Expect ';' after value.`
+///
+/// Messages of errors jlox doesn't have are unchanged.
+pub fn jlox_message(message: &str) -> String {
+    let jlox = match message {
+        "Expected variable name" => "Expect variable name.",
+        "Expected ';' after variable declaration" => "Expect ';' after variable declaration.",
+        "Expect ';' after value" => "Expect ';' after value.",
+        "Expect ';' after expression" => "Expect ';' after expression.",
+        "Expected '}' after block" => "Expect '}' after block.",
+        "Expected '(' after 'if'" => "Expect '(' after 'if'.",
+        "Expected ')' after if condition" => "Expect ')' after if condition.",
+        "Expected '(' after 'while'" => "Expect '(' after 'while'.",
+        "Expected ')' after condition" => "Expect ')' after condition.",
+        "Expected '(' after 'for'" => "Expect '(' after 'for'.",
+        "Expected ';' after loop condition" => "Expect ';' after loop condition.",
+        "Expected ')' after for clauses" => "Expect ')' after for clauses.",
+        "Expected ')' after arguments" => "Expect ')' after arguments.",
+        "Expected ')' after expression" => "Expect ')' after expression.",
+        "Expected property name after '.'" => "Expect property name after '.'.",
+        "Expected expression" => "Expect expression.",
+        "Cannot have more than 255 arguments" => "Can't have more than 255 arguments.",
+        "Invalid assignment target" => "Invalid assignment target.",
+        "Unterminated string" => "Unterminated string.",
+        "Operand must be a number" => "Operand must be a number.",
+        "Operands must be numbers" => "Operands must be numbers.",
+        "Operands must be two numbers or two strings" => {
+            "Operands must be two numbers or two strings."
+        }
+        "Can only call functions and classes" => "Can only call functions and classes.",
+        _ => return jlox_formatted_message(message),
+    };
+    String::from(jlox)
+}
+
+/// The jlox wording of the messages that contain a name or a number
+fn jlox_formatted_message(message: &str) -> String {
+    if let Some(name) = message.strip_prefix("Undeclared variable ") {
+        format!("Undefined variable {name}.")
+    } else if message.starts_with("Unexpected character ") {
+        String::from("Unexpected character.")
+    } else if is_arity_message(message) || message.starts_with("Undefined property ") {
+        format!("{message}.")
+    } else {
+        String::from(message)
+    }
+}
+
+/// `Expected 2 arguments but got 1` is also the jlox wording
+fn is_arity_message(message: &str) -> bool {
+    message
+        .strip_prefix("Expected ")
+        .and_then(|rest| rest.split_once(" arguments but got "))
+        .is_some_and(|(expected, _)| expected.parse::<usize>().is_ok())
+}
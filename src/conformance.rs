@@ -0,0 +1,127 @@
+use crate::golden::{self, Outcome};
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+};
+
+// The Crafting Interpreters test suite groups its tests by feature,
+// these are the chapters of the book that introduce each feature.
+// Chapters 4 and 7 are left out, upstream only tests them with special builds of jlox.
+
+const CHAPTERS: [(usize, &str, &[&str]); 6] = [
+    (
+        8,
+        "Statements and State",
+        &[
+            "assignment",
+            "block",
+            "bool",
+            "comments",
+            "nil",
+            "number",
+            "operator",
+            "print",
+            "string",
+            "variable",
+            "empty_file.lox",
+            "precedence.lox",
+        ],
+    ),
+    (
+        9,
+        "Control Flow",
+        &["if", "logical_operator", "while", "for"],
+    ),
+    (
+        10,
+        "Functions",
+        &[
+            "call",
+            "function",
+            "return",
+            "closure",
+            "unexpected_character.lox",
+        ],
+    ),
+    (11, "Resolving and Binding", &[]),
+    (
+        12,
+        "Classes",
+        &["class", "constructor", "field", "method", "this"],
+    ),
+    (13, "Inheritance", &["inheritance", "super"]),
+];
+
+/// Tests that need a feature from a later chapter than the one of their directory
+const LATER_CHAPTER: [(&str, usize); 3] = [
+    ("for/syntax.lox", 10),
+    ("variable/use_local_in_initializer.lox", 11),
+    ("return/at_top_level.lox", 11),
+];
+
+pub struct Chapter {
+    pub number: usize,
+    pub name: &'static str,
+    pub total: usize,
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl Display for Chapter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Chapter {:>2} {:<24} {}/{} passed",
+            self.number,
+            self.name,
+            self.total - self.failures.len(),
+            self.total
+        )
+    }
+}
+
+/// Runs every test of the suite in compat mode, grouped by chapter
+///
+/// Files outside of the known directories are ignored.
+pub fn run(root: &Path) -> io::Result<Vec<Chapter>> {
+    let mut chapters: Vec<Chapter> = CHAPTERS
+        .iter()
+        .map(|(number, name, _)| Chapter {
+            number: *number,
+            name,
+            total: 0,
+            failures: Vec::new(),
+        })
+        .collect();
+    for file in golden::collect(&[root.to_path_buf()])? {
+        let Some(number) = chapter_of(root, &file) else {
+            continue;
+        };
+        let chapter = chapters
+            .iter_mut()
+            .find(|chapter| chapter.number == number)
+            .expect("LATER_CHAPTER should only use chapters from CHAPTERS");
+        chapter.total += 1;
+        if let Outcome::Fail(diff) = golden::run_file(&file, true)? {
+            chapter.failures.push((file, diff));
+        }
+    }
+    Ok(chapters)
+}
+
+fn chapter_of(root: &Path, file: &Path) -> Option<usize> {
+    let relative = file.strip_prefix(root).ok()?;
+    let name = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    if let Some((_, number)) = LATER_CHAPTER.iter().find(|(path, _)| *path == name) {
+        return Some(*number);
+    }
+    let first = name.split('/').next()?;
+    CHAPTERS
+        .iter()
+        .find(|(_, _, directories)| directories.contains(&first))
+        .map(|(number, _, _)| *number)
+}
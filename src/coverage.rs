@@ -0,0 +1,198 @@
+use crate::{
+    ast::{Expr, Stmt},
+    token::Position,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+
+// Records the statements and the `if` branches a program runs, in the lcov format
+// read by coverage viewers.
+//
+// The statements are registered before the program runs, so the ones that never
+// run are in the report with 0 hits. An `if` has two branches, the then branch
+// and the else branch, even when it doesn't have an `else`.
+//
+// A line is hit once each time a statement starting on it runs. The `if` of
+// `let a = if b { 1 } else { 2 };` runs as part of the `let`, it isn't counted again.
+
+pub struct Coverage {
+    file: PathBuf,
+    /// The number of statements run on each line
+    lines: BTreeMap<usize, usize>,
+    /// How many times each `if` took its then and its else branch,
+    /// keyed by the position of its keyword
+    branches: BTreeMap<(usize, usize), [usize; 2]>,
+    /// The lines of the statements running, the innermost last, `None` for blocks
+    running: Vec<Option<usize>>,
+    /// The next statement to run is the value of an expression
+    is_expression: bool,
+}
+
+impl Coverage {
+    pub fn new(file: PathBuf) -> Self {
+        Coverage {
+            file,
+            lines: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            running: Vec::new(),
+            is_expression: false,
+        }
+    }
+
+    /// Adds the lines of the statements and their branches, without hits
+    pub fn register(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            match stmt {
+                Stmt::Block(statements) => self.register(statements),
+                Stmt::If(keyword, condition, then_branch, else_branch) => {
+                    self.lines.entry(keyword.position.line).or_default();
+                    self.branches.entry(key(keyword.position)).or_default();
+                    self.register_expression(condition);
+                    self.register(std::slice::from_ref(then_branch));
+                    if let Some(else_branch) = else_branch {
+                        self.register(std::slice::from_ref(else_branch));
+                    }
+                }
+                Stmt::While(_, _, condition, body, increment) => {
+                    self.register_line(stmt);
+                    self.register_expression(condition);
+                    self.register(std::slice::from_ref(body));
+                    if let Some(increment) = increment {
+                        self.register_expression(increment);
+                    }
+                }
+                Stmt::ForIn(_, _, _, iterable, body) => {
+                    self.register_line(stmt);
+                    self.register_expression(iterable);
+                    self.register(std::slice::from_ref(body));
+                }
+                Stmt::Expression(expr)
+                | Stmt::Print(_, expr)
+                | Stmt::Let(_, Some(expr))
+                | Stmt::Break(_, _, Some(expr)) => {
+                    self.register_line(stmt);
+                    self.register_expression(expr);
+                }
+                Stmt::Let(..) | Stmt::Break(..) | Stmt::Continue(..) => self.register_line(stmt),
+            }
+        }
+    }
+
+    /// Adds the statements used as expressions, like the `if` of `let a = if b { 1 } else { 2 };`
+    fn register_expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Statement(stmt) => self.register(std::slice::from_ref(stmt)),
+            Expr::Binary(left, _, right)
+            | Expr::Logical(left, _, right)
+            | Expr::Range(left, _, right)
+            | Expr::Index(left, _, right) => {
+                self.register_expression(left);
+                self.register_expression(right);
+            }
+            Expr::Grouping(expr)
+            | Expr::Unary(_, expr)
+            | Expr::Get(expr, _)
+            | Expr::Assign(_, expr) => self.register_expression(expr),
+            Expr::Call(callee, _, args) => {
+                self.register_expression(callee);
+                for arg in args {
+                    self.register_expression(arg);
+                }
+            }
+            Expr::SetIndex(object, _, index, value) => {
+                self.register_expression(object);
+                self.register_expression(index);
+                self.register_expression(value);
+            }
+            Expr::List(_, items) => {
+                for item in items {
+                    self.register_expression(item);
+                }
+            }
+            Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.register_expression(key);
+                    self.register_expression(value);
+                }
+            }
+            Expr::Literal(_) | Expr::Variable(_) => (),
+        }
+    }
+
+    fn register_line(&mut self, stmt: &Stmt) {
+        if let Some(position) = stmt.position() {
+            self.lines.entry(position.line).or_default();
+        }
+    }
+
+    /// The next statement runs as the value of an expression of the statement running
+    pub fn enter_expression(&mut self) {
+        self.is_expression = true;
+    }
+
+    /// Hits the line of a statement about to run, `None` for a block, until `exit_line`
+    pub fn enter_line(&mut self, line: Option<usize>) {
+        let is_expression = std::mem::take(&mut self.is_expression);
+        if let Some(line) = line {
+            // Already hit by the statement of the expression
+            if !(is_expression && self.running.last() == Some(&Some(line))) {
+                *self.lines.entry(line).or_default() += 1;
+            }
+        }
+        self.running.push(line);
+    }
+
+    /// The statement of the last `enter_line` is done
+    pub fn exit_line(&mut self) {
+        self.running.pop();
+    }
+
+    /// Records the branch taken by the `if` with this keyword position
+    pub fn hit_branch(&mut self, position: Position, is_then_branch: bool) {
+        // The then branch is the first one
+        let branch = usize::from(!is_then_branch);
+        self.branches.entry(key(position)).or_default()[branch] += 1;
+    }
+}
+
+fn key(position: Position) -> (usize, usize) {
+    (position.line, position.column)
+}
+
+/// The lcov record of the file
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "TN:")?;
+        writeln!(f, "SF:{}", self.file.display())?;
+        // lcov numbers the blocks of branches, each `if` is a block
+        for (block, ((line, _), taken)) in self.branches.iter().enumerate() {
+            let is_evaluated = taken.iter().any(|count| *count > 0);
+            for (branch, count) in taken.iter().enumerate() {
+                if is_evaluated {
+                    writeln!(f, "BRDA:{line},{block},{branch},{count}")?;
+                } else {
+                    // `-` is a branch whose condition never ran
+                    writeln!(f, "BRDA:{line},{block},{branch},-")?;
+                }
+            }
+        }
+        let branches_hit = self
+            .branches
+            .values()
+            .flatten()
+            .filter(|count| **count > 0)
+            .count();
+        writeln!(f, "BRF:{}", self.branches.len() * 2)?;
+        writeln!(f, "BRH:{branches_hit}")?;
+        for (line, hits) in &self.lines {
+            writeln!(f, "DA:{line},{hits}")?;
+        }
+        let lines_hit = self.lines.values().filter(|hits| **hits > 0).count();
+        writeln!(f, "LF:{}", self.lines.len())?;
+        writeln!(f, "LH:{lines_hit}")?;
+        writeln!(f, "end_of_record")
+    }
+}
@@ -0,0 +1,399 @@
+use crate::token::{Token, TokenType};
+use derive_new::new;
+use std::{
+    fmt::{Display, Formatter, Result},
+    iter::Peekable,
+    vec::IntoIter,
+};
+
+// The cst is lossless, the text of the tree is exactly the scanned source.
+// It only exists for tooling, the interpreter keeps using the ast from `Parser`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    /// Text the scanner reported as an error, like an unexpected character
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+impl Trivia {
+    pub fn new(text: &str) -> Self {
+        let kind = if text.starts_with("//") {
+            TriviaKind::LineComment
+        } else if text.starts_with("/*") {
+            TriviaKind::BlockComment
+        } else if text.chars().all(|c| c == '\n') {
+            TriviaKind::Newline
+        } else if text.chars().all(char::is_whitespace) {
+            TriviaKind::Whitespace
+        } else {
+            TriviaKind::Skipped
+        };
+        Trivia {
+            kind,
+            text: String::from(text),
+        }
+    }
+
+    pub fn is_newline(&self) -> bool {
+        self.kind == TriviaKind::Newline
+    }
+
+    /// Consecutive whitespace and newlines are merged in a single trivia
+    pub fn can_merge(&self, text: &str) -> bool {
+        match self.kind {
+            TriviaKind::Whitespace | TriviaKind::Newline => Trivia::new(text).kind == self.kind,
+            _ => false,
+        }
+    }
+}
+
+#[derive(new, Clone)]
+pub struct SyntaxToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub trailing: Vec<Trivia>,
+}
+
+impl SyntaxToken {
+    pub fn token_type(&self) -> TokenType {
+        self.token.token_type
+    }
+
+    pub fn write_text(&self, text: &mut String) {
+        self.leading
+            .iter()
+            .for_each(|trivia| text.push_str(&trivia.text));
+        text.push_str(&self.token.lexeme);
+        self.trailing
+            .iter()
+            .for_each(|trivia| text.push_str(&trivia.text));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Program,
+    Let,
+    Print,
+    Expression,
+    If,
+    While,
+    Loop,
+    For,
+    Block,
+    Break,
+    Continue,
+    /// Expressions are flat, `Parser` already knows how to build their structure
+    Expr,
+    /// Tokens that can't start a statement
+    Error,
+}
+
+#[derive(Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(new, Clone)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    /// The source text of the node, including all its trivia
+    #[allow(dead_code)]
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for token in self.tokens() {
+            token.write_text(&mut text);
+        }
+        text
+    }
+
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    fn fmt(&self, f: &mut Formatter<'_>, depth: usize) -> Result {
+        writeln!(f, "{}{:?}", "    ".repeat(depth), self.kind)?;
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.fmt(f, depth + 1)?,
+                SyntaxElement::Token(token) => {
+                    write!(
+                        f,
+                        "{}{:?} {:?}",
+                        "    ".repeat(depth + 1),
+                        token.token.token_type,
+                        token.token.lexeme
+                    )?;
+                    let trivia = |trivia: &[Trivia]| -> Vec<String> {
+                        trivia.iter().map(|trivia| trivia.text.clone()).collect()
+                    };
+                    if !token.leading.is_empty() {
+                        write!(f, " leading: {:?}", trivia(&token.leading))?;
+                    }
+                    if !token.trailing.is_empty() {
+                        write!(f, " trailing: {:?}", trivia(&token.trailing))?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.fmt(f, 0)
+    }
+}
+
+/// Builds the cst of a lossless token stream, see `Scanner::scan_lossless`
+///
+/// This never fails, tokens that don't fit the grammar end up in `Error` nodes.
+pub fn build(tokens: Vec<SyntaxToken>) -> SyntaxNode {
+    let mut builder = Builder {
+        tokens: tokens.into_iter().peekable(),
+    };
+    builder.program()
+}
+
+struct Builder {
+    tokens: Peekable<IntoIter<SyntaxToken>>,
+}
+
+impl Builder {
+    fn program(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        while !self.check(TokenType::EOF) {
+            if self.check(TokenType::RIGHT_BRACE) {
+                let token = self.bump();
+                children.push(SyntaxElement::Node(SyntaxNode::new(
+                    NodeKind::Error,
+                    vec![token],
+                )));
+            } else {
+                children.push(SyntaxElement::Node(self.statement()));
+            }
+        }
+        children.push(self.bump());
+        SyntaxNode::new(NodeKind::Program, children)
+    }
+
+    fn statement(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        let kind = match self.peek() {
+            TokenType::LET => {
+                self.let_declaration(&mut children);
+                NodeKind::Let
+            }
+            TokenType::PRINT => {
+                children.push(self.bump());
+                self.expression(&mut children, &[]);
+                self.expect(&mut children, TokenType::SEMICOLON);
+                NodeKind::Print
+            }
+            TokenType::IF => {
+                children.push(self.bump());
+                self.expression(&mut children, &[TokenType::LEFT_BRACE]);
+                self.block(&mut children);
+                if self.check(TokenType::ELSE) {
+                    children.push(self.bump());
+                    if self.check(TokenType::IF) {
+                        children.push(SyntaxElement::Node(self.statement()));
+                    } else {
+                        self.block(&mut children);
+                    }
+                }
+                NodeKind::If
+            }
+            TokenType::WHILE => {
+                children.push(self.bump());
+                self.expression(&mut children, &[TokenType::LEFT_BRACE]);
+                self.block(&mut children);
+                NodeKind::While
+            }
+            TokenType::LOOP => {
+                children.push(self.bump());
+                self.block(&mut children);
+                NodeKind::Loop
+            }
+            TokenType::FOR => {
+                self.for_statement(&mut children);
+                NodeKind::For
+            }
+            TokenType::LEFT_BRACE => {
+                self.block(&mut children);
+                NodeKind::Block
+            }
+            // The label is the first token of its loop
+            TokenType::LABEL => {
+                children.push(self.bump());
+                self.expect(&mut children, TokenType::COLON);
+                let labeled = self.statement();
+                children.extend(labeled.children);
+                labeled.kind
+            }
+            TokenType::BREAK | TokenType::CONTINUE => {
+                let kind = if self.check(TokenType::BREAK) {
+                    NodeKind::Break
+                } else {
+                    NodeKind::Continue
+                };
+                children.push(self.bump());
+                self.expect(&mut children, TokenType::LABEL);
+                if kind == NodeKind::Break {
+                    // The value given to the loop
+                    self.expression(&mut children, &[]);
+                }
+                self.expect(&mut children, TokenType::SEMICOLON);
+                kind
+            }
+            _ => {
+                if self.expression(&mut children, &[]) {
+                    self.expect(&mut children, TokenType::SEMICOLON);
+                    NodeKind::Expression
+                } else {
+                    children.push(self.bump());
+                    NodeKind::Error
+                }
+            }
+        };
+        SyntaxNode::new(kind, children)
+    }
+
+    fn let_declaration(&mut self, children: &mut Vec<SyntaxElement>) {
+        children.push(self.bump());
+        self.expect(children, TokenType::IDENTIFIER);
+        if self.check(TokenType::EQUAL) {
+            children.push(self.bump());
+            self.expression(children, &[]);
+        }
+        self.expect(children, TokenType::SEMICOLON);
+    }
+
+    fn for_statement(&mut self, children: &mut Vec<SyntaxElement>) {
+        children.push(self.bump());
+        if self.check(TokenType::IDENTIFIER) {
+            children.push(self.bump());
+            self.expect(children, TokenType::IN);
+            self.expression(children, &[TokenType::LEFT_BRACE]);
+            self.block(children);
+            return;
+        }
+        self.expect(children, TokenType::LEFT_PAREN);
+        if self.check(TokenType::LET) {
+            let mut initializer = Vec::new();
+            self.let_declaration(&mut initializer);
+            children.push(SyntaxElement::Node(SyntaxNode::new(
+                NodeKind::Let,
+                initializer,
+            )));
+        } else if !self.check(TokenType::SEMICOLON) {
+            let mut initializer = Vec::new();
+            self.expression(&mut initializer, &[]);
+            self.expect(&mut initializer, TokenType::SEMICOLON);
+            children.push(SyntaxElement::Node(SyntaxNode::new(
+                NodeKind::Expression,
+                initializer,
+            )));
+        } else {
+            children.push(self.bump());
+        }
+        self.expression(children, &[]);
+        self.expect(children, TokenType::SEMICOLON);
+        self.expression(children, &[TokenType::RIGHT_PAREN]);
+        self.expect(children, TokenType::RIGHT_PAREN);
+        if !self.check(TokenType::EOF) {
+            children.push(SyntaxElement::Node(self.statement()));
+        }
+    }
+
+    fn block(&mut self, children: &mut Vec<SyntaxElement>) {
+        let mut block = Vec::new();
+        if !self.expect(&mut block, TokenType::LEFT_BRACE) {
+            return;
+        }
+        while !self.check(TokenType::RIGHT_BRACE) && !self.check(TokenType::EOF) {
+            block.push(SyntaxElement::Node(self.statement()));
+        }
+        self.expect(&mut block, TokenType::RIGHT_BRACE);
+        children.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Block, block)));
+    }
+
+    /// Collects the tokens up to a `;`, a `}` or one of the terminators outside of parentheses,
+    /// brackets and braces, the blocks of `loop` and `if` expressions are part of the expression
+    fn expression(&mut self, children: &mut Vec<SyntaxElement>, terminators: &[TokenType]) -> bool {
+        let mut expr = Vec::new();
+        let mut depth = 0_usize;
+        loop {
+            let token_type = self.peek();
+            let is_terminator = token_type == TokenType::SEMICOLON
+                || token_type == TokenType::RIGHT_BRACE
+                || terminators.contains(&token_type);
+            if token_type == TokenType::EOF || (depth == 0 && is_terminator) {
+                break;
+            }
+            match token_type {
+                TokenType::LEFT_PAREN | TokenType::LEFT_BRACE | TokenType::LEFT_BRACKET => {
+                    depth += 1;
+                }
+                TokenType::RIGHT_PAREN | TokenType::RIGHT_BRACE | TokenType::RIGHT_BRACKET => {
+                    depth = depth.saturating_sub(1);
+                }
+                _ => (),
+            }
+            expr.push(self.bump());
+        }
+        if expr.is_empty() {
+            false
+        } else {
+            children.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Expr, expr)));
+            true
+        }
+    }
+
+    fn expect(&mut self, children: &mut Vec<SyntaxElement>, token_type: TokenType) -> bool {
+        if self.check(token_type) {
+            children.push(self.bump());
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check(&mut self, token_type: TokenType) -> bool {
+        self.peek() == token_type
+    }
+
+    fn peek(&mut self) -> TokenType {
+        self.tokens
+            .peek()
+            .map_or(TokenType::EOF, SyntaxToken::token_type)
+    }
+
+    fn bump(&mut self) -> SyntaxElement {
+        SyntaxElement::Token(self.tokens.next().expect("bump should not go past EOF"))
+    }
+}
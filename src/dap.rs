@@ -0,0 +1,295 @@
+use crate::{
+    debugger::{Breakpoint, Debugger, Frontend},
+    environment::Environment,
+    logger::{DapLogger, LoggerImpl},
+    lox::Lox,
+    transport::Transport,
+};
+use serde_json::{json, Value};
+use std::{cell::RefCell, convert::TryFrom, fs, path::PathBuf, rc::Rc};
+
+// A Debug Adapter Protocol server, the protocol used by VS Code to talk to debuggers.
+// Messages are json objects exchanged over stdio, see `transport`.
+//
+// The server is single threaded, requests are only read before the program starts
+// and while it is paused. Lox has no threads, there is always a single thread and
+// a single stack frame, the scopes of the frame are the environments
+// from the current one to the globals.
+
+/// Lox has a single thread, requests about threads always use this id
+const THREAD_ID: u64 = 1;
+
+pub struct Connection {
+    transport: Transport,
+    seq: u64,
+}
+
+impl Connection {
+    pub fn new(transport: Transport) -> Self {
+        Connection { transport, seq: 0 }
+    }
+
+    pub fn read(&mut self) -> Option<Value> {
+        self.transport.read()
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        self.transport.write(&message);
+    }
+
+    pub fn respond(&mut self, request: &Value, body: &Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    pub fn respond_error(&mut self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    pub fn event(&mut self, event: &str, body: &Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+}
+
+pub fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or("")
+}
+
+/// Serves a debugging session, from the `initialize` request to the `disconnect` request
+///
+/// The program starts once it is launched and its breakpoints are configured.
+pub fn run(connection: &Rc<RefCell<Connection>>, is_compat: bool) {
+    let mut program = None;
+    let mut stop_on_entry = false;
+    let mut is_configured = false;
+    let mut breakpoints = Vec::new();
+    while program.is_none() || !is_configured {
+        let Some(request) = connection.borrow_mut().read() else {
+            return;
+        };
+        let mut connection = connection.borrow_mut();
+        match command(&request) {
+            "initialize" => {
+                connection.respond(
+                    &request,
+                    &json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsSetVariable": true,
+                    }),
+                );
+                connection.event("initialized", &json!({}));
+            }
+            "launch" => {
+                let arguments = &request["arguments"];
+                let Some(path) = arguments["program"].as_str() else {
+                    connection.respond_error(&request, "Missing program to launch");
+                    continue;
+                };
+                program = Some(PathBuf::from(path));
+                stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                connection.respond(&request, &json!({}));
+            }
+            "setBreakpoints" => {
+                let body = set_breakpoints(&mut breakpoints, &request);
+                connection.respond(&request, &body);
+            }
+            "configurationDone" => {
+                is_configured = true;
+                connection.respond(&request, &json!({}));
+            }
+            "threads" => connection.respond(&request, &threads()),
+            "disconnect" => {
+                connection.respond(&request, &json!({}));
+                return;
+            }
+            _ => connection.respond_error(&request, "Unsupported request"),
+        }
+    }
+
+    let program = program.expect("program should be Some() once launched");
+    let exit_code = match fs::read_to_string(&program) {
+        Ok(source) => debug(
+            connection,
+            program,
+            &source,
+            breakpoints,
+            stop_on_entry,
+            is_compat,
+        ),
+        Err(error) => {
+            output(
+                connection,
+                &format!("Failed to read {}: {}", program.display(), error),
+            );
+            66
+        }
+    };
+    {
+        let mut connection = connection.borrow_mut();
+        connection.event("exited", &json!({ "exitCode": exit_code }));
+        connection.event("terminated", &json!({}));
+    }
+
+    // The client can still ask for threads until it disconnects
+    loop {
+        let Some(request) = connection.borrow_mut().read() else {
+            break;
+        };
+        let mut connection = connection.borrow_mut();
+        match command(&request) {
+            "threads" => connection.respond(&request, &threads()),
+            "disconnect" => {
+                connection.respond(&request, &json!({}));
+                break;
+            }
+            _ => connection.respond_error(&request, "The program is done"),
+        }
+    }
+}
+
+/// Runs the program with a debugger attached, returns the exit code of the program
+fn debug(
+    connection: &Rc<RefCell<Connection>>,
+    program: PathBuf,
+    source: &str,
+    breakpoints: Vec<Breakpoint>,
+    stop_on_entry: bool,
+    is_compat: bool,
+) -> i32 {
+    let mut logger = DapLogger::new(connection.clone());
+    logger.is_compat = is_compat;
+    let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
+    let mut lox = Lox::new(&logger, false);
+    lox.set_compat(is_compat);
+    let mut debugger = Debugger::new(
+        program,
+        source,
+        breakpoints,
+        Frontend::Dap(connection.clone()),
+    );
+    debugger.set_stop_on_entry(stop_on_entry);
+    lox.interpreter.debugger = Some(debugger);
+    lox.run(source).err().map_or(0, |error| error.exit_code())
+}
+
+fn output(connection: &Rc<RefCell<Connection>>, message: &str) {
+    connection.borrow_mut().event(
+        "output",
+        &json!({ "category": "stdout", "output": format!("{}\n", message) }),
+    );
+}
+
+pub fn threads() -> Value {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
+
+/// Replaces the breakpoints of the file of a `setBreakpoints` request,
+/// returns the body of its response
+pub fn set_breakpoints(breakpoints: &mut Vec<Breakpoint>, request: &Value) -> Value {
+    let arguments = &request["arguments"];
+    let file = PathBuf::from(arguments["source"]["path"].as_str().unwrap_or(""));
+    let lines: Vec<usize> = arguments["breakpoints"]
+        .as_array()
+        .map(|requested| {
+            requested
+                .iter()
+                .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                .filter_map(|line| usize::try_from(line).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    breakpoints.retain(|breakpoint| breakpoint.file != file);
+    breakpoints.extend(lines.iter().map(|line| Breakpoint {
+        file: file.clone(),
+        line: *line,
+    }));
+    json!({
+        "breakpoints": lines
+            .iter()
+            .map(|line| json!({ "verified": true, "line": line }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+pub fn stopped(reason: &str) -> Value {
+    json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true })
+}
+
+pub fn stack_trace(file: &PathBuf, line: usize) -> Value {
+    let name = file
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    json!({
+        "stackFrames": [{
+            "id": 0,
+            "name": "<script>",
+            "line": line,
+            "column": 1,
+            "source": { "name": name, "path": file },
+        }],
+        "totalFrames": 1,
+    })
+}
+
+/// The environments from the current one to the globals, following `Environment.enclosing`
+fn scope_chain(env: &Rc<RefCell<Environment>>) -> Vec<Rc<RefCell<Environment>>> {
+    let mut chain = vec![env.clone()];
+    loop {
+        let enclosing = chain[chain.len() - 1].borrow().enclosing.clone();
+        match enclosing {
+            Some(enclosing) => chain.push(enclosing),
+            None => return chain,
+        }
+    }
+}
+
+/// One scope per environment, the reference of a scope is its index in the chain plus one,
+/// 0 means a variable has no children
+pub fn scopes(env: &Rc<RefCell<Environment>>) -> Value {
+    let chain = scope_chain(env);
+    let scopes: Vec<Value> = (0..chain.len())
+        .map(|i| {
+            let name = if i == chain.len() - 1 {
+                String::from("Globals")
+            } else if i == 0 {
+                String::from("Locals")
+            } else {
+                format!("Enclosing {i}")
+            };
+            json!({ "name": name, "variablesReference": i + 1, "expensive": false })
+        })
+        .collect();
+    json!({ "scopes": scopes })
+}
+
+pub fn variables(env: &Rc<RefCell<Environment>>, reference: usize) -> Option<Value> {
+    let chain = scope_chain(env);
+    let scope = chain.get(reference.checked_sub(1)?)?;
+    let variables: Vec<Value> = scope
+        .borrow()
+        .values()
+        .into_iter()
+        .map(|(name, value)| {
+            json!({ "name": name, "value": value.to_string(), "variablesReference": 0 })
+        })
+        .collect();
+    Some(json!({ "variables": variables }))
+}
@@ -0,0 +1,378 @@
+use crate::{
+    dap::{self, Connection},
+    environment::Environment,
+    lox::LoxValue,
+};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    convert::TryFrom,
+    io::BufRead,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const HELP: &str = "\
+step, s                Run the next statement, stepping into blocks
+next, n                Run the next statement, stepping over blocks
+finish, f              Run until the current block is done
+continue, c            Run until the next breakpoint
+break, b [file:]line   Add a breakpoint
+delete, d [file:]line  Remove a breakpoint
+breakpoints            List the breakpoints
+print, p <expr>        Evaluate an expression in the current scope
+set <name> = <expr>    Assign a variable in the current scope
+env                    Print the variables of the current scope and the enclosing ones
+list, l                Print the source around the current line
+help, h                Show this help
+quit, q                Stop the program";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Breakpoint {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl Breakpoint {
+    /// Parses `file:line`, or only `line` for a breakpoint in `default_file`
+    pub fn parse(text: &str, default_file: &Path) -> Option<Self> {
+        let (file, line) = match text.rsplit_once(':') {
+            Some((file, line)) => (PathBuf::from(file), line),
+            None => (default_file.to_path_buf(), text),
+        };
+        Some(Breakpoint {
+            file,
+            line: line.trim().parse().ok()?,
+        })
+    }
+
+    /// Breakpoints can use any suffix of the path, `a.lox:3` matches `examples/a.lox`
+    fn matches(&self, file: &Path, line: usize) -> bool {
+        self.line == line && file.ends_with(&self.file)
+    }
+}
+
+/// How far the program runs before pausing again
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    /// Pauses at a statement at this depth or less
+    Next(usize),
+    /// Pauses at a statement at less than this depth
+    Finish(usize),
+    Continue,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Resumes the program
+    Resume,
+    Print(String),
+    Env,
+    Quit,
+}
+
+/// Where the commands come from while the program is paused
+pub enum Frontend {
+    /// Text commands like `step` or `print a`, see `HELP`
+    Console(Box<dyn BufRead>),
+    /// Requests of the debug adapter protocol, see `dap`
+    Dap(Rc<RefCell<Connection>>),
+}
+
+pub struct Debugger {
+    file: PathBuf,
+    source: Vec<String>,
+    breakpoints: BTreeSet<Breakpoint>,
+    mode: Mode,
+    frontend: Frontend,
+    /// The dap request waiting for the value of an expression
+    evaluating: Option<Value>,
+}
+
+impl Debugger {
+    /// Without breakpoints the debugger pauses before the first statement
+    pub fn new(
+        file: PathBuf,
+        source: &str,
+        breakpoints: Vec<Breakpoint>,
+        frontend: Frontend,
+    ) -> Self {
+        let mode = if breakpoints.is_empty() {
+            Mode::Step
+        } else {
+            Mode::Continue
+        };
+        Debugger {
+            file,
+            source: source.lines().map(String::from).collect(),
+            breakpoints: breakpoints.into_iter().collect(),
+            mode,
+            frontend,
+            evaluating: None,
+        }
+    }
+
+    /// Overrides the default of `new`, when false the program runs until a breakpoint
+    pub fn set_stop_on_entry(&mut self, stop_on_entry: bool) {
+        self.mode = if stop_on_entry {
+            Mode::Step
+        } else {
+            Mode::Continue
+        };
+    }
+
+    fn is_breakpoint(&self, line: usize) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.matches(&self.file, line))
+    }
+
+    /// Checks if a statement at this line and block depth is a place to pause
+    pub fn should_pause(&self, line: usize, depth: usize) -> bool {
+        self.is_breakpoint(line)
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next(next_depth) => depth <= next_depth,
+                Mode::Finish(finish_depth) => depth < finish_depth,
+                Mode::Continue => false,
+            }
+    }
+
+    /// Where the program is paused, with the source of the line
+    fn location(&self, line: usize) -> String {
+        let text = self.source.get(line - 1).map_or("", |text| text.trim());
+        format!("{}:{}    {}", self.file.display(), line, text)
+    }
+
+    /// Tells the frontend that the program paused at this line
+    pub fn paused(&mut self, line: usize, output: &mut Vec<String>) {
+        match &self.frontend {
+            Frontend::Console(_) => output.push(format!("Paused at {}", self.location(line))),
+            Frontend::Dap(connection) => {
+                let reason = if self.is_breakpoint(line) {
+                    "breakpoint"
+                } else {
+                    "step"
+                };
+                connection
+                    .borrow_mut()
+                    .event("stopped", &dap::stopped(reason));
+            }
+        }
+    }
+
+    /// Gives the result of a `Command::Print` to the frontend, `None` if it failed
+    pub fn evaluated(&mut self, value: Option<LoxValue>, output: &mut Vec<String>) {
+        match (&self.frontend, value) {
+            (Frontend::Console(_), Some(value)) => output.push(value.to_string()),
+            (Frontend::Console(_), None) => (),
+            (Frontend::Dap(connection), value) => {
+                let Some(request) = self.evaluating.take() else {
+                    return;
+                };
+                let mut connection = connection.borrow_mut();
+                match value {
+                    Some(value) if dap::command(&request) == "setVariable" => {
+                        connection.respond(&request, &json!({ "value": value.to_string() }));
+                    }
+                    Some(value) => connection.respond(
+                        &request,
+                        &json!({ "result": value.to_string(), "variablesReference": 0 }),
+                    ),
+                    None => connection.respond_error(&request, "Could not evaluate the expression"),
+                }
+            }
+        }
+    }
+
+    /// Reads commands until one of them needs the interpreter
+    ///
+    /// `output` receives what the debugger prints by itself.
+    /// At the end of the input the program runs to the end without pausing.
+    pub fn read_command(
+        &mut self,
+        line: usize,
+        depth: usize,
+        env: &Rc<RefCell<Environment>>,
+        output: &mut Vec<String>,
+    ) -> Command {
+        if let Frontend::Dap(connection) = &self.frontend {
+            let connection = connection.clone();
+            return self.read_request(&connection, line, depth, env);
+        }
+        loop {
+            let Some(text) = self.read_line() else {
+                self.breakpoints.clear();
+                self.mode = Mode::Continue;
+                return Command::Resume;
+            };
+            let text = text.trim();
+            let (name, argument) = match text.split_once(char::is_whitespace) {
+                Some((name, argument)) => (name, argument.trim()),
+                None => (text, ""),
+            };
+            let mode = match name {
+                "step" | "s" => Mode::Step,
+                "next" | "n" => Mode::Next(depth),
+                "finish" | "f" => Mode::Finish(depth),
+                "continue" | "c" => Mode::Continue,
+                // Setting a variable is evaluating an assignment
+                "print" | "p" | "set" => return Command::Print(String::from(argument)),
+                "env" => return Command::Env,
+                "quit" | "q" => return Command::Quit,
+                "break" | "b" | "delete" | "d" => {
+                    output.push(self.edit_breakpoint(name, argument));
+                    continue;
+                }
+                "breakpoints" => {
+                    output.extend(self.breakpoints.iter().map(|breakpoint| {
+                        format!("{}:{}", breakpoint.file.display(), breakpoint.line)
+                    }));
+                    continue;
+                }
+                "list" | "l" => {
+                    output.push(self.list(line));
+                    continue;
+                }
+                "help" | "h" => {
+                    output.push(String::from(HELP));
+                    continue;
+                }
+                "" => continue,
+                _ => {
+                    output.push(format!("Unknown command {name}, try help"));
+                    continue;
+                }
+            };
+            self.mode = mode;
+            return Command::Resume;
+        }
+    }
+
+    /// `None` at the end of the input
+    fn read_line(&mut self) -> Option<String> {
+        let Frontend::Console(input) = &mut self.frontend else {
+            return None;
+        };
+        let mut text = String::new();
+        match input.read_line(&mut text) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(text),
+        }
+    }
+
+    /// Answers dap requests until one of them needs the interpreter
+    ///
+    /// Disconnecting, or the end of the input, stops the program.
+    fn read_request(
+        &mut self,
+        connection: &Rc<RefCell<Connection>>,
+        line: usize,
+        depth: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Command {
+        loop {
+            let Some(request) = connection.borrow_mut().read() else {
+                return Command::Quit;
+            };
+            let arguments = &request["arguments"];
+            let mode = match dap::command(&request) {
+                "continue" => Mode::Continue,
+                "next" => Mode::Next(depth),
+                "stepIn" => Mode::Step,
+                "stepOut" => Mode::Finish(depth),
+                "evaluate" => {
+                    let expression = arguments["expression"].as_str().unwrap_or("");
+                    let expression = String::from(expression);
+                    self.evaluating = Some(request);
+                    return Command::Print(expression);
+                }
+                // Like `set` in the console, the innermost variable with the name is assigned
+                "setVariable" => {
+                    let assignment = format!(
+                        "{} = {}",
+                        arguments["name"].as_str().unwrap_or(""),
+                        arguments["value"].as_str().unwrap_or("")
+                    );
+                    self.evaluating = Some(request);
+                    return Command::Print(assignment);
+                }
+                "disconnect" => {
+                    connection.borrow_mut().respond(&request, &json!({}));
+                    return Command::Quit;
+                }
+                _ => {
+                    let body = self.describe(&request, line, env);
+                    let mut connection = connection.borrow_mut();
+                    match body {
+                        Some(body) => connection.respond(&request, &body),
+                        None => connection.respond_error(&request, "Unsupported request"),
+                    }
+                    continue;
+                }
+            };
+            connection
+                .borrow_mut()
+                .respond(&request, &json!({ "allThreadsContinued": true }));
+            self.mode = mode;
+            return Command::Resume;
+        }
+    }
+
+    /// The body of the response to a request that leaves the program paused
+    fn describe(
+        &mut self,
+        request: &Value,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Option<Value> {
+        match dap::command(request) {
+            "threads" => Some(dap::threads()),
+            "stackTrace" => Some(dap::stack_trace(&self.file, line)),
+            "scopes" => Some(dap::scopes(env)),
+            "variables" => {
+                let reference = request["arguments"]["variablesReference"].as_u64()?;
+                dap::variables(env, usize::try_from(reference).ok()?)
+            }
+            "setBreakpoints" => {
+                let mut breakpoints = self.breakpoints.iter().cloned().collect();
+                let body = dap::set_breakpoints(&mut breakpoints, request);
+                self.breakpoints = breakpoints.into_iter().collect();
+                Some(body)
+            }
+            // The program is already paused
+            "pause" => Some(json!({})),
+            _ => None,
+        }
+    }
+
+    fn edit_breakpoint(&mut self, name: &str, argument: &str) -> String {
+        let Some(breakpoint) = Breakpoint::parse(argument, &self.file) else {
+            return format!("Invalid breakpoint \"{argument}\", expected [file:]line");
+        };
+        let description = format!("{}:{}", breakpoint.file.display(), breakpoint.line);
+        if name.starts_with('b') {
+            self.breakpoints.insert(breakpoint);
+            format!("Breakpoint at {description}")
+        } else if self.breakpoints.remove(&breakpoint) {
+            format!("Deleted breakpoint at {description}")
+        } else {
+            format!("No breakpoint at {description}")
+        }
+    }
+
+    /// The lines around the current one, the current one is marked with `>`
+    fn list(&self, line: usize) -> String {
+        let start = line.saturating_sub(2).max(1);
+        let end = (line + 2).min(self.source.len());
+        (start..=end)
+            .map(|number| {
+                let marker = if number == line { ">" } else { " " };
+                format!("{} {:>4} {}", marker, number, self.source[number - 1])
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
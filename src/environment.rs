@@ -2,18 +2,19 @@ use crate::{
     lox::{ErrorData, LoxError, LoxResult, LoxValue},
     token::Token,
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    rc::Rc,
+};
 
 // TODO
 // * put each env in a list and reference the id
 // * env could have a scope type
-// * add continue() and break().
-//      They should unwind the env to the nearest loop scope
-//      WARN This will break the desugared for loop
 
 #[derive(Debug, Default, Clone)]
 pub struct Environment {
-    pub is_loop: bool, // TODO scope type: function, loop, block
     pub enclosing: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, LoxValue>,
 }
@@ -23,26 +24,27 @@ impl Environment {
         Environment {
             values: HashMap::new(),
             enclosing: Some(enclosing.clone()),
-            is_loop: enclosing.borrow().is_loop,
         }
     }
 
-    pub fn is_inside_loop(&self) -> bool {
-        if self.is_loop {
-            true
-        } else {
-            match self.enclosing {
-                Some(ref enclosing) => enclosing.borrow().is_inside_loop(),
-                None => false,
-            }
+    /// Every name visible from this environment, including the enclosing ones
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(ref enclosing) = self.enclosing {
+            names.extend(enclosing.borrow().names());
         }
+        names
     }
 
-    pub fn is_enclosing_loop(&self) -> bool {
-        match self.enclosing {
-            Some(ref enclosing) => enclosing.borrow().is_loop,
-            None => false,
-        }
+    /// The variables of this environment only, sorted by name
+    pub fn values(&self) -> Vec<(String, LoxValue)> {
+        let mut values: Vec<(String, LoxValue)> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        values
     }
 
     pub fn declare(&mut self, name: &str, value: LoxValue) {
@@ -75,3 +77,18 @@ impl Environment {
         }
     }
 }
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.values.keys().collect();
+        names.sort();
+        for name in names {
+            writeln!(f, "{} = {}", name, self.values[name])?;
+        }
+        if let Some(ref enclosing) = self.enclosing {
+            writeln!(f, "-- enclosing")?;
+            enclosing.borrow().fmt(f)?;
+        }
+        Ok(())
+    }
+}
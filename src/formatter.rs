@@ -0,0 +1,301 @@
+use crate::{
+    cst::{SyntaxToken, Trivia, TriviaKind},
+    parser::is_map_start,
+    token::TokenType,
+};
+
+const INDENT: &str = "    ";
+
+/// Pretty prints a lossless token stream back to canonical lox source, keeping the comments
+///
+/// The tokens are expected to come from a program that parsed successfully.
+/// Working on tokens instead of the ast keeps the syntax exactly as it was written,
+/// the ast desugars `for` and `loop` and doesn't know about comments.
+pub fn format(tokens: &[SyntaxToken]) -> String {
+    let pieces = pieces(tokens);
+    let mut formatter = Formatter::default();
+    for (i, piece) in pieces.iter().enumerate() {
+        formatter.write_piece(*piece, pieces.get(i + 1).copied());
+    }
+    if !formatter.output.is_empty() {
+        formatter.output.push('\n');
+    }
+    formatter.output
+}
+
+/// A token or a comment, whitespace is the only trivia that doesn't survive formatting
+#[derive(Clone, Copy)]
+struct Piece<'t> {
+    text: &'t str,
+    /// `None` for comments
+    token_type: Option<TokenType>,
+    newlines_before: usize,
+    /// A brace of a map literal, the entries stay on one line like the items of a list
+    is_map: bool,
+}
+
+impl Piece<'_> {
+    fn is_comment(self) -> bool {
+        self.token_type.is_none()
+    }
+
+    fn is_line_comment(self) -> bool {
+        self.is_comment() && self.text.starts_with("//")
+    }
+
+    fn is(self, token_type: TokenType) -> bool {
+        self.token_type == Some(token_type)
+    }
+
+    fn is_block_brace(self, token_type: TokenType) -> bool {
+        self.is(token_type) && !self.is_map
+    }
+}
+
+fn pieces(tokens: &[SyntaxToken]) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut newlines = 0;
+    for token in tokens {
+        for trivia in &token.leading {
+            push_trivia(&mut pieces, &mut newlines, trivia);
+        }
+        if token.token_type() != TokenType::EOF {
+            pieces.push(Piece {
+                text: &token.token.lexeme,
+                token_type: Some(token.token_type()),
+                newlines_before: newlines,
+                is_map: false,
+            });
+            newlines = 0;
+        }
+        for trivia in &token.trailing {
+            push_trivia(&mut pieces, &mut newlines, trivia);
+        }
+    }
+    mark_maps(&mut pieces);
+    pieces
+}
+
+/// Finds the braces of the maps, like the parser does for a `{` in an expression
+///
+/// A `{` after a condition, an iterable or at the start of a statement is a block.
+fn mark_maps(pieces: &mut [Piece<'_>]) {
+    let token_types: Vec<TokenType> = pieces.iter().filter_map(|piece| piece.token_type).collect();
+    let mut braces = Vec::new();
+    let mut previous: Option<TokenType> = None;
+    let mut index = 0;
+    for piece in pieces.iter_mut() {
+        let Some(token_type) = piece.token_type else {
+            continue;
+        };
+        index += 1;
+        match token_type {
+            TokenType::LEFT_BRACE => {
+                let is_expression = previous.is_some_and(|previous| {
+                    !ends_operand_type(previous)
+                        && !matches!(
+                            previous,
+                            TokenType::SEMICOLON
+                                | TokenType::LEFT_BRACE
+                                | TokenType::RIGHT_BRACE
+                                | TokenType::ELSE
+                                | TokenType::LOOP
+                        )
+                });
+                piece.is_map = is_expression && is_map_start(token_types[index..].iter().copied());
+                braces.push(piece.is_map);
+            }
+            TokenType::RIGHT_BRACE => piece.is_map = braces.pop().unwrap_or(false),
+            _ => (),
+        }
+        previous = Some(token_type);
+    }
+}
+
+fn push_trivia<'t>(pieces: &mut Vec<Piece<'t>>, newlines: &mut usize, trivia: &'t Trivia) {
+    match trivia.kind {
+        TriviaKind::Whitespace => (),
+        TriviaKind::Newline => *newlines += trivia.text.len(),
+        TriviaKind::LineComment | TriviaKind::BlockComment | TriviaKind::Skipped => {
+            pieces.push(Piece {
+                text: &trivia.text,
+                token_type: None,
+                newlines_before: *newlines,
+                is_map: false,
+            });
+            *newlines = 0;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Formatter<'t> {
+    output: String,
+    depth: usize,
+    paren_depth: usize,
+    previous: Option<Piece<'t>>,
+    newline: bool,
+    unary_minus: bool,
+}
+
+impl<'t> Formatter<'t> {
+    fn write_piece(&mut self, piece: Piece<'t>, next: Option<Piece<'t>>) {
+        let is_trailing_comment =
+            piece.is_comment() && self.previous.is_some() && piece.newlines_before == 0;
+
+        if is_trailing_comment {
+            self.output.push(' ');
+        } else if self.newline || self.is_on_new_line(piece) || self.is_block_end(piece) {
+            self.write_newline(piece);
+        } else if self.needs_space(piece) {
+            self.output.push(' ');
+        }
+
+        if piece.is(TokenType::MINUS) {
+            self.unary_minus = !self.previous.is_some_and(ends_operand);
+        }
+        self.output.push_str(piece.text);
+        self.previous = Some(piece);
+        self.newline = if is_trailing_comment {
+            self.newline || piece.is_line_comment()
+        } else {
+            self.needs_newline_after(piece, next)
+        };
+    }
+
+    fn write_newline(&mut self, piece: Piece<'t>) {
+        if piece.is_block_brace(TokenType::RIGHT_BRACE) {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        if let Some(previous) = self.previous {
+            self.output.push('\n');
+            let is_block_edge =
+                previous.is(TokenType::LEFT_BRACE) || piece.is(TokenType::RIGHT_BRACE);
+            if piece.newlines_before > 1 && !is_block_edge {
+                self.output.push('\n');
+            }
+        }
+        for _ in 0..self.depth {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    /// Comments keep the line breaks they had in the source
+    fn is_on_new_line(&self, piece: Piece<'t>) -> bool {
+        match self.previous {
+            Some(previous) if previous.is_comment() || piece.is_comment() => {
+                piece.newlines_before > 0
+            }
+            _ => false,
+        }
+    }
+
+    /// The `}` of a block ending with an expression, its value, doesn't follow a `;`
+    fn is_block_end(&self, piece: Piece<'t>) -> bool {
+        piece.is_block_brace(TokenType::RIGHT_BRACE)
+            && self
+                .previous
+                .is_some_and(|previous| !previous.is(TokenType::LEFT_BRACE))
+    }
+
+    fn needs_space(&self, piece: Piece<'t>) -> bool {
+        let (Some(previous), Some(token_type)) = (self.previous, piece.token_type) else {
+            return self.previous.is_some();
+        };
+        let Some(previous_type) = previous.token_type else {
+            return true;
+        };
+        if (previous.is_map && previous_type == TokenType::LEFT_BRACE)
+            || (piece.is_map && token_type == TokenType::RIGHT_BRACE)
+        {
+            return false;
+        }
+        if matches!(
+            token_type,
+            TokenType::SEMICOLON
+                | TokenType::COLON
+                | TokenType::COMMA
+                | TokenType::RIGHT_PAREN
+                | TokenType::RIGHT_BRACKET
+                | TokenType::DOT
+                | TokenType::DOT_DOT
+                | TokenType::DOT_DOT_EQUAL
+        ) {
+            return false;
+        }
+        match (previous_type, token_type) {
+            (
+                TokenType::LEFT_PAREN
+                | TokenType::LEFT_BRACKET
+                | TokenType::DOT
+                | TokenType::DOT_DOT
+                | TokenType::DOT_DOT_EQUAL
+                | TokenType::BANG,
+                _,
+            )
+            | (TokenType::LEFT_BRACE, TokenType::RIGHT_BRACE)
+            | (TokenType::IDENTIFIER | TokenType::RIGHT_PAREN, TokenType::LEFT_PAREN) => false,
+            // Indexing, a list literal is not after an operand
+            (_, TokenType::LEFT_BRACKET) => !self.previous.is_some_and(ends_operand),
+            (TokenType::MINUS, _) => !self.unary_minus,
+            _ => true,
+        }
+    }
+
+    fn needs_newline_after(&mut self, piece: Piece<'t>, next: Option<Piece<'t>>) -> bool {
+        let Some(token_type) = piece.token_type else {
+            return piece.is_line_comment();
+        };
+        let next_type = next.and_then(|next| next.token_type);
+        match token_type {
+            TokenType::LEFT_PAREN => {
+                self.paren_depth += 1;
+                false
+            }
+            TokenType::RIGHT_PAREN => {
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                false
+            }
+            TokenType::SEMICOLON => self.paren_depth == 0,
+            TokenType::LEFT_BRACE | TokenType::RIGHT_BRACE if piece.is_map => false,
+            TokenType::LEFT_BRACE => {
+                if next_type == Some(TokenType::RIGHT_BRACE) {
+                    false
+                } else {
+                    self.depth += 1;
+                    true
+                }
+            }
+            TokenType::RIGHT_BRACE => match next_type {
+                Some(
+                    TokenType::ELSE
+                    | TokenType::SEMICOLON
+                    | TokenType::RIGHT_PAREN
+                    | TokenType::COMMA,
+                ) => false,
+                _ => self.paren_depth == 0,
+            },
+            _ => false,
+        }
+    }
+}
+
+fn ends_operand(piece: Piece<'_>) -> bool {
+    piece.token_type.is_some_and(ends_operand_type)
+        || (piece.is_map && piece.is(TokenType::RIGHT_BRACE))
+}
+
+fn ends_operand_type(token_type: TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::IDENTIFIER
+            | TokenType::NUMBER
+            | TokenType::STRING
+            | TokenType::TRUE
+            | TokenType::FALSE
+            | TokenType::NIL
+            | TokenType::THIS
+            | TokenType::RIGHT_PAREN
+            | TokenType::RIGHT_BRACKET
+    )
+}
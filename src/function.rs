@@ -1,26 +1,42 @@
-use crate::{interpreter::Interpreter, lox::LoxValue};
+use crate::{interpreter::Interpreter, lox::LoxValue, methods};
 use std::fmt::{self, Display, Formatter};
 
+/// The body of a native, errors are messages like the errors of `call`
+type NativeBody = fn(&[LoxValue]) -> Result<LoxValue, String>;
+
 #[derive(Clone)]
 pub enum Function {
-    Native(usize, Box<fn(&[LoxValue]) -> LoxValue>),
+    Native(usize, Box<NativeBody>),
+    /// A method of a builtin type with the value it was taken from, see `methods`
+    Method(Box<LoxValue>, String),
 }
 
 impl Function {
     pub fn arity(&self) -> usize {
         match self {
             Function::Native(arity, _) => *arity,
+            Function::Method(receiver, name) => {
+                methods::arity(receiver, name).expect("methods should have an arity")
+            }
         }
     }
-    pub fn call(&self, _interpreter: &mut Interpreter, args: &[LoxValue]) -> LoxValue {
+
+    /// Errors are messages, the caller knows where the call is
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[LoxValue],
+    ) -> Result<LoxValue, String> {
         match self {
             Function::Native(_, body) => body(args),
+            Function::Method(receiver, name) => methods::call(interpreter, receiver, name, args),
         }
     }
 
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Function::Native(_, _) => write!(f, "<native fn>"),
+            Function::Method(_, name) => write!(f, "<method {name}>"),
         }
     }
 }
@@ -0,0 +1,207 @@
+use crate::{
+    logger::{LoggerImpl, TestLogger},
+    lox::Lox,
+};
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+// Golden files are lox scripts annotated with the output they should produce,
+// in the same style as the Crafting Interpreters test suite:
+//
+//     print 1 + 2; // expect: 3
+//     print -"a"; // expect runtime error: Operand must be a number
+//     let = 1; // expect error at '=': Expected variable name
+//
+// `// expect error` annotations can also give the line explicitly,
+// `// [line 3] expect error at end: ...`, which is needed for errors reported on
+// another line, like at the end of the file.
+//
+// In compat mode the annotations and the errors are the ones of the upstream suite,
+// `// [line 3] Error at end: ...`, and the output is compared as jlox prints it.
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "expect error";
+/// Starts the error annotations, which can be followed by an explicit line
+const COMMENT: &str = "// ";
+/// Errors only reported by clox, the C implementation of the book
+const CLOX_ONLY: &str = "[c line ";
+/// Errors only reported by jlox
+const JLOX_ONLY: &str = "[java line ";
+
+pub enum Outcome {
+    Pass,
+    /// Contains the diff between the expected and the actual output
+    Fail(String),
+}
+
+/// Finds the `.lox` files in the paths, directories are walked recursively
+pub fn collect(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            files.extend(collect(&entries)?);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Runs a golden file and compares its output to its annotations
+pub fn run_file(path: &Path, is_compat: bool) -> io::Result<Outcome> {
+    let source = fs::read_to_string(path)?;
+    let expected = if is_compat {
+        compat_expectations(&source)
+    } else {
+        expectations(&source)
+    };
+
+    let mut output = Vec::new();
+    {
+        let mut logger = TestLogger::new(&mut output);
+        logger.is_compat = is_compat;
+        let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
+        let mut lox = Lox::new(&logger, false);
+        lox.set_compat(is_compat);
+        // Errors are part of the output, they are checked like everything else
+        lox.run(&source).ok();
+    }
+    let output = String::from_utf8_lossy(&output);
+    let actual: Vec<String> = if is_compat {
+        output.lines().map(String::from).collect()
+    } else {
+        output.lines().map(normalize).collect()
+    };
+
+    if expected == actual {
+        Ok(Outcome::Pass)
+    } else {
+        Ok(Outcome::Fail(diff(&expected, &actual)))
+    }
+}
+
+/// The expected output lines, errors are in the format returned by `normalize`
+fn expectations(source: &str) -> Vec<String> {
+    let mut expected = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        if let Some(index) = line.find(EXPECT) {
+            expected.push(String::from(&line[index + EXPECT.len()..]));
+        } else if let Some(index) = line.find(EXPECT_RUNTIME_ERROR) {
+            let message = &line[index + EXPECT_RUNTIME_ERROR.len()..];
+            expected.push(format!("[line {line_number}] runtime error: {message}"));
+        } else if let Some(index) = line.find(COMMENT) {
+            let comment = &line[index + COMMENT.len()..];
+            let (line_number, comment) = match explicit_line(comment) {
+                Some((explicit, comment)) => (explicit, comment),
+                None => (line_number, comment),
+            };
+            if let Some(error) = comment.strip_prefix(EXPECT_ERROR) {
+                if error.starts_with(':') || error.starts_with(" at ") {
+                    expected.push(format!("[line {line_number}] error{error}"));
+                }
+            }
+        }
+    }
+    expected
+}
+
+/// The expected output lines with the annotations of the upstream suite
+///
+/// jlox reports a runtime error with its message followed by its line.
+fn compat_expectations(source: &str) -> Vec<String> {
+    let mut expected = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        if let Some(index) = line.find(EXPECT) {
+            expected.push(String::from(&line[index + EXPECT.len()..]));
+        } else if let Some(index) = line.find(EXPECT_RUNTIME_ERROR) {
+            expected.push(String::from(&line[index + EXPECT_RUNTIME_ERROR.len()..]));
+            expected.push(format!("[line {line_number}]"));
+        } else if let Some(index) = line.find(COMMENT) {
+            let comment = &line[index + COMMENT.len()..];
+            if comment.starts_with(CLOX_ONLY) {
+                continue;
+            }
+            let comment = comment.replacen(JLOX_ONLY, "[line ", 1);
+            let (line_number, error) = match explicit_line(&comment) {
+                Some((explicit, error)) => (explicit, error),
+                None => (line_number, comment.as_str()),
+            };
+            if error.starts_with("Error:") || error.starts_with("Error at ") {
+                expected.push(format!("[line {line_number}] {error}"));
+            }
+        }
+    }
+    expected
+}
+
+/// Splits `[line 3] expect error: ...` in its line and the rest of the comment
+fn explicit_line(comment: &str) -> Option<(usize, &str)> {
+    let rest = comment.strip_prefix("[line ")?;
+    let (line, error) = rest.split_once("] ")?;
+    Some((line.parse().ok()?, error))
+}
+
+/// Turns a reported error like `[ln 3 col 5] ParserError at 'a': message`
+/// into `[line 3] error at 'a': message`, other output is unchanged
+///
+/// Columns are left out, the annotations only know about lines.
+fn normalize(line: &str) -> String {
+    let error = line.strip_prefix("[ln ").and_then(|rest| {
+        let (position, error) = rest.split_once("] ")?;
+        let (line_number, _) = position.split_once(" col ")?;
+        let (tag, rest) = error.split_once("Error ")?;
+        let kind = match tag {
+            "Runtime" | "Panic!" => "runtime error",
+            _ => "error",
+        };
+        let separator = if rest.starts_with(':') { "" } else { " " };
+        Some(format!("[line {line_number}] {kind}{separator}{rest}"))
+    });
+    error.unwrap_or_else(|| String::from(line))
+}
+
+/// A line diff based on the longest common subsequence
+///
+/// Lines only in `expected` start with `-`, lines only in `actual` with `+`.
+pub fn diff(expected: &[String], actual: &[String]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+    // lengths[i][j] is the length of the lcs of expected[i..] and actual[j..]
+    let mut lengths = vec![vec![0_usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,189 @@
+use crate::token::{TokenType, KEYWORDS};
+use serde_json::{json, Value};
+
+// Syntax highlighting for editors, generated from `TokenType` so it can't drift from the scanner.
+//
+// The TextMate grammar is used by VS Code before the language server starts and for
+// comments, the semantic tokens of the language server then take over.
+// The grammar in editors/vscode is generated with `lox_rs grammar`,
+// a test checks that it is up to date.
+
+/// The semantic token types of the language server, `Highlight::semantic_type` indexes it
+pub const LEGEND: [&str; 5] = ["keyword", "operator", "variable", "string", "number"];
+
+/// Identifiers start with a letter or `_`, digits start numbers, see `Scanner::identifier`
+const IDENTIFIER: &str = r"[\p{Alpha}_][\p{Alnum}_]*";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    /// `if`, `while`, `break`...
+    Control,
+    /// Keywords introducing a declaration, like `let`
+    Declaration,
+    /// `true`, `false` and `nil`
+    Constant,
+    /// `this` and `super`
+    Language,
+    /// Keywords used like functions, `print`
+    Builtin,
+    /// `and` and `or`
+    LogicalOperator,
+    Operator,
+    Punctuation,
+    Identifier,
+    /// `'outer` in `'outer: loop` and `break 'outer`
+    Label,
+    String,
+    Number,
+    /// Tokens without text
+    None,
+}
+
+impl Highlight {
+    /// Every token type is listed, a new one doesn't compile until it has a highlight
+    pub fn of(token_type: TokenType) -> Self {
+        match token_type {
+            TokenType::LEFT_PAREN
+            | TokenType::RIGHT_PAREN
+            | TokenType::LEFT_BRACE
+            | TokenType::RIGHT_BRACE
+            | TokenType::LEFT_BRACKET
+            | TokenType::RIGHT_BRACKET
+            | TokenType::COMMA
+            | TokenType::DOT
+            | TokenType::SEMICOLON
+            | TokenType::COLON => Highlight::Punctuation,
+            TokenType::DOT_DOT
+            | TokenType::DOT_DOT_EQUAL
+            | TokenType::MINUS
+            | TokenType::PLUS
+            | TokenType::SLASH
+            | TokenType::STAR
+            | TokenType::BANG
+            | TokenType::BANG_EQUAL
+            | TokenType::EQUAL
+            | TokenType::EQUAL_EQUAL
+            | TokenType::GREATER
+            | TokenType::GREATER_EQUAL
+            | TokenType::LESS
+            | TokenType::LESS_EQUAL => Highlight::Operator,
+            TokenType::IDENTIFIER => Highlight::Identifier,
+            TokenType::LABEL => Highlight::Label,
+            TokenType::STRING => Highlight::String,
+            TokenType::NUMBER => Highlight::Number,
+            TokenType::AND | TokenType::OR => Highlight::LogicalOperator,
+            TokenType::CLASS | TokenType::FUN | TokenType::LET => Highlight::Declaration,
+            TokenType::ELSE
+            | TokenType::FOR
+            | TokenType::IF
+            | TokenType::RETURN
+            | TokenType::WHILE
+            | TokenType::LOOP
+            | TokenType::BREAK
+            | TokenType::CONTINUE
+            | TokenType::IN => Highlight::Control,
+            TokenType::FALSE | TokenType::NIL | TokenType::TRUE => Highlight::Constant,
+            TokenType::SUPER | TokenType::THIS => Highlight::Language,
+            TokenType::PRINT => Highlight::Builtin,
+            TokenType::EOF => Highlight::None,
+        }
+    }
+
+    /// The textmate scope, from the naming conventions shared by the themes
+    fn scope(self) -> Option<&'static str> {
+        match self {
+            Highlight::Control => Some("keyword.control.lox"),
+            Highlight::Declaration => Some("storage.type.lox"),
+            Highlight::Constant => Some("constant.language.lox"),
+            Highlight::Language => Some("variable.language.lox"),
+            Highlight::Builtin => Some("support.function.builtin.lox"),
+            Highlight::LogicalOperator => Some("keyword.operator.logical.lox"),
+            Highlight::Operator => Some("keyword.operator.lox"),
+            Highlight::Punctuation => Some("punctuation.lox"),
+            Highlight::Identifier => Some("variable.other.lox"),
+            Highlight::Label => Some("entity.name.label.lox"),
+            Highlight::String => Some("string.quoted.double.lox"),
+            Highlight::Number => Some("constant.numeric.lox"),
+            Highlight::None => None,
+        }
+    }
+
+    /// The index of the semantic token type in `LEGEND`, punctuation and labels are left
+    /// to the grammar
+    pub fn semantic_type(self) -> Option<usize> {
+        let name = match self {
+            Highlight::Control
+            | Highlight::Declaration
+            | Highlight::Constant
+            | Highlight::Language
+            | Highlight::Builtin
+            | Highlight::LogicalOperator => "keyword",
+            Highlight::Operator => "operator",
+            Highlight::Identifier => "variable",
+            Highlight::String => "string",
+            Highlight::Number => "number",
+            Highlight::Punctuation | Highlight::Label | Highlight::None => return None,
+        };
+        LEGEND.iter().position(|legend| *legend == name)
+    }
+}
+
+/// One pattern matching every keyword with this highlight, `var` is matched like `let`
+fn keywords(highlight: Highlight) -> Option<Value> {
+    let words: Vec<&str> = KEYWORDS
+        .iter()
+        .filter(|(_, token_type)| Highlight::of(*token_type) == highlight)
+        .map(|(word, _)| *word)
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "name": highlight.scope(),
+        "match": format!(r"(?<![\p{{Alnum}}_])(?:{})(?![\p{{Alnum}}_])", words.join("|")),
+    }))
+}
+
+/// The textmate grammar of lox, in the json format of VS Code
+pub fn textmate_grammar() -> Value {
+    let mut patterns = vec![
+        json!({ "name": "comment.line.double-slash.lox", "match": "//.*$" }),
+        json!({ "name": "comment.block.lox", "begin": r"/\*", "end": r"\*/" }),
+        json!({ "name": Highlight::String.scope(), "begin": "\"", "end": "\"" }),
+        json!({ "name": Highlight::Number.scope(), "match": r"\b[0-9]+(?:\.[0-9]+)?\b" }),
+    ];
+    let highlights = [
+        Highlight::Control,
+        Highlight::Declaration,
+        Highlight::Constant,
+        Highlight::Language,
+        Highlight::Builtin,
+        Highlight::LogicalOperator,
+    ];
+    patterns.extend(
+        highlights
+            .iter()
+            .filter_map(|highlight| keywords(*highlight)),
+    );
+    patterns.extend(vec![
+        json!({ "name": Highlight::Label.scope(), "match": format!("'{}", IDENTIFIER) }),
+        json!({ "name": Highlight::Identifier.scope(), "match": IDENTIFIER }),
+        json!({ "name": Highlight::Operator.scope(), "match": r"==|!=|<=|>=|\.\.=?|[-+*/=<>!]" }),
+        json!({ "name": Highlight::Punctuation.scope(), "match": r"[(){}\[\],.;:]" }),
+    ]);
+    json!({
+        "$schema": "https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json",
+        "name": "Lox",
+        "scopeName": "source.lox",
+        "fileTypes": ["lox"],
+        "patterns": patterns,
+    })
+}
+
+/// The grammar as written to editors/vscode
+pub fn grammar_json() -> String {
+    let mut json =
+        serde_json::to_string_pretty(&textmate_grammar()).expect("the grammar should serialize");
+    json.push('\n');
+    json
+}
@@ -1,10 +1,19 @@
 use crate::{
     ast::{Expr, Stmt, StmtResult},
+    coverage::Coverage,
+    debugger::{Command, Debugger},
     environment::Environment,
     function::Function,
     logger::{Logger, LoggerImpl},
     lox::{ErrorData, LoxError, LoxResult, LoxValue},
+    map::{Key, Map},
+    methods,
+    parser::Parser,
+    profiler::Profiler,
+    range::Range,
+    scanner::Scanner,
     token::{Token, TokenType},
+    tracer::Tracer,
 };
 use float_cmp::{ApproxEq, F64Margin};
 use std::{
@@ -16,6 +25,122 @@ use std::{
 pub struct Interpreter<'a> {
     pub environment: Rc<RefCell<Environment>>,
     logger: &'a Rc<RefCell<LoggerImpl<'a>>>,
+    /// Follows the jlox semantics, see `compat`
+    pub is_compat: bool,
+    pub debugger: Option<Debugger>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub tracer: Option<Tracer>,
+    /// How many blocks deep the current statement is, for the debugger
+    depth: usize,
+}
+
+/// The line the debugger, the profiler and the coverage know a statement by
+///
+/// Blocks don't have one, their first statement is on the same line.
+fn statement_line(stmt: &Stmt) -> Option<usize> {
+    match stmt {
+        Stmt::Block(_) => None,
+        _ => stmt.position().map(|position| position.line),
+    }
+}
+
+fn label_name(label: Option<&Token>) -> Option<String> {
+    label.map(|label| label.lexeme.clone())
+}
+
+/// A `break` or `continue` without a label targets the innermost loop
+fn is_target(label: Option<&Token>, target: Option<&String>) -> bool {
+    target.is_none_or(|target| label.is_some_and(|label| label.lexeme == *target))
+}
+
+/// What a loop returns when the result of its body stops it, `None` to keep looping
+fn loop_exit(label: Option<&Token>, result: StmtResult) -> Option<StmtResult> {
+    match result {
+        StmtResult::Break(target, value) if is_target(label, target.as_ref()) => Some(value.into()),
+        StmtResult::Continue(target) if is_target(label, target.as_ref()) => None,
+        // The target is an outer loop
+        StmtResult::Break(..) | StmtResult::Continue(_) => Some(result),
+        StmtResult::Value(_) => None,
+    }
+}
+
+/// The item of a list at an index, the value of a key of a map, or the items taken
+/// by the indices of a range, `"hello"[1..3]` is `"el"`
+fn index_value(object: &LoxValue, index: &LoxValue) -> Result<LoxValue, String> {
+    match (object, index) {
+        (LoxValue::Map(map), key) => {
+            let key = Key::new(key.clone())?;
+            map.borrow()
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| format!("Undefined key {key}"))
+        }
+        (LoxValue::List(list), LoxValue::Number(index)) => {
+            let list = list.borrow();
+            Ok(list[item_index(*index, list.len())?].clone())
+        }
+        (LoxValue::List(list), LoxValue::Range(range)) => {
+            let list = list.borrow();
+            let slice: Result<Vec<LoxValue>, String> = range
+                .iter()
+                .map(|index| item_index(index, list.len()).map(|index| list[index].clone()))
+                .collect();
+            Ok(slice?.into())
+        }
+        (LoxValue::String(value), LoxValue::Number(index)) => {
+            let chars: Vec<char> = value.chars().collect();
+            Ok(chars[item_index(*index, chars.len())?].to_string().into())
+        }
+        (LoxValue::String(value), LoxValue::Range(range)) => {
+            let chars: Vec<char> = value.chars().collect();
+            let slice: Result<String, String> = range
+                .iter()
+                .map(|index| item_index(index, chars.len()).map(|index| chars[index]))
+                .collect();
+            Ok(slice?.into())
+        }
+        (LoxValue::List(_) | LoxValue::String(_), _) => {
+            Err(String::from("Indices must be numbers or ranges"))
+        }
+        _ => Err(String::from("Can only index lists, maps and strings")),
+    }
+}
+
+/// Replaces the item of a list at an index, or sets the value of a key of a map
+fn set_index_value(object: &LoxValue, index: &LoxValue, value: LoxValue) -> Result<(), String> {
+    match (object, index) {
+        (LoxValue::Map(map), key) => {
+            map.borrow_mut().insert(Key::new(key.clone())?, value);
+            Ok(())
+        }
+        (LoxValue::List(list), LoxValue::Number(index)) => {
+            let mut list = list.borrow_mut();
+            let index = item_index(*index, list.len())?;
+            list[index] = value;
+            Ok(())
+        }
+        (LoxValue::List(_), _) => Err(String::from("Indices must be numbers")),
+        _ => Err(String::from("Can only assign to list items and map keys")),
+    }
+}
+
+/// Checks that a number is an index of a collection of this length,
+/// a negative index counts from the end, `-1` is the last item
+pub fn item_index(index: f64, len: usize) -> Result<usize, String> {
+    #[allow(clippy::cast_precision_loss)]
+    let len_f64 = len as f64;
+    let from_start = if index < 0.0 { index + len_f64 } else { index };
+    if from_start < 0.0
+        || from_start >= len_f64
+        || !from_start.fract().approx_eq(0.0, F64Margin::default())
+    {
+        return Err(format!(
+            "Index {index} is out of bounds for a length of {len}"
+        ));
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok(from_start as usize)
 }
 
 fn init_globals() -> Environment {
@@ -26,80 +151,153 @@ fn init_globals() -> Environment {
             0,
             Box::new(|_| {
                 #[allow(clippy::cast_precision_loss)]
-                LoxValue::from(
+                Ok(LoxValue::from(
                     SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .expect("Could not retrieve time.")
                         .as_millis() as f64,
-                )
+                ))
             }),
         )
         .into(),
     );
+    // Two arguments like the function of `reduce`, `numbers.reduce(max, 0)`
+    globals.declare(
+        &String::from("min"),
+        Function::Native(
+            2,
+            Box::new(|args| two_numbers(args).map(|(a, b)| a.min(b).into())),
+        )
+        .into(),
+    );
+    globals.declare(
+        &String::from("max"),
+        Function::Native(
+            2,
+            Box::new(|args| two_numbers(args).map(|(a, b)| a.max(b).into())),
+        )
+        .into(),
+    );
     globals
 }
 
+fn two_numbers(args: &[LoxValue]) -> Result<(f64, f64), String> {
+    match args {
+        [LoxValue::Number(a), LoxValue::Number(b)] => Ok((*a, *b)),
+        _ => Err(String::from("Operands must be numbers")),
+    }
+}
+
 impl<'a> Interpreter<'a> {
     pub fn new(logger: &'a Rc<RefCell<LoggerImpl<'a>>>) -> Self {
         let globals = Rc::new(RefCell::new(init_globals()));
         Interpreter {
             logger,
             environment: globals,
+            is_compat: false,
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            tracer: None,
+            depth: 0,
         }
     }
 
-    pub fn interpret(&mut self, statements: &[Stmt]) {
+    /// Runs the statements, stops at the first `LoxError::Panic` and returns it
+    ///
+    /// In compat mode every runtime error stops the program, like jlox
+    pub fn interpret(&mut self, statements: &[Stmt]) -> LoxResult<()> {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.register(statements);
+        }
         for statement in statements {
-            if let Err(error) = self.execute(statement, self.environment.clone()) {
-                self.logger.borrow_mut().runtime_error(error);
+            match self.execute(statement, self.environment.clone()) {
+                Ok(StmtResult::Value(LoxValue::Unit)) => (),
+                Ok(StmtResult::Value(value)) => {
+                    self.logger.borrow_mut().println_repl(format!("{value}"));
+                }
+                Ok(_) => (),
+                Err(error) => {
+                    self.logger.borrow_mut().runtime_error(&error);
+                    if self.is_compat || matches!(error, LoxError::Panic(_)) {
+                        return Err(error);
+                    }
+                }
             }
         }
+        Ok(())
     }
 
     fn execute(&mut self, stmt: &Stmt, env: Rc<RefCell<Environment>>) -> LoxResult<StmtResult> {
+        if self.debugger.is_some() {
+            self.pause(stmt, &env);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.enter_line(statement_line(stmt));
+        }
+        if let Some(tracer) = &self.tracer {
+            self.trace(tracer.statement(stmt));
+        }
+        let result = match (self.profiler.as_mut(), statement_line(stmt)) {
+            (Some(profiler), Some(line)) => {
+                profiler.enter_line(line);
+                let result = self.execute_statement(stmt, env);
+                self.profiler
+                    .as_mut()
+                    .expect("profiler should still be Some()")
+                    .exit();
+                result
+            }
+            _ => self.execute_statement(stmt, env),
+        };
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.exit_line();
+        }
+        match result {
+            // A `break` or `continue` in an expression ends the statement of the expression
+            Err(LoxError::Control(result)) => Ok(result),
+            result => result,
+        }
+    }
+
+    fn execute_statement(
+        &mut self,
+        stmt: &Stmt,
+        env: Rc<RefCell<Environment>>,
+    ) -> LoxResult<StmtResult> {
         match stmt {
             Stmt::Expression(expr) => {
-                let value = self.evaluate(&expr, &mut env.borrow_mut())?;
-                self.logger.borrow_mut().println_repl(format!("{}", value));
-                Ok(LoxValue::Unit.into())
+                let value = self.evaluate(expr, &env)?;
+                Ok(value.into())
             }
-            Stmt::Print(expr) => {
-                let value = self.evaluate(&expr, &mut env.borrow_mut())?;
-                self.logger.borrow_mut().println(format!("{}", value));
+            Stmt::Print(_, expr) => {
+                let value = self.evaluate(expr, &env)?;
+                self.logger.borrow_mut().println(format!("{value}"));
                 Ok(LoxValue::Unit.into())
             }
             Stmt::Let(token, initializer) => {
                 let value = match initializer {
-                    Some(inializer_value) => {
-                        self.evaluate(&inializer_value, &mut env.borrow_mut())?
-                    }
+                    Some(inializer_value) => self.evaluate(inializer_value, &env)?,
                     None => LoxValue::Nil,
                 };
+                if let Some(tracer) = &self.tracer {
+                    self.trace(tracer.declaration(token, &value));
+                }
                 env.borrow_mut().declare(&token.lexeme, value);
                 Ok(LoxValue::Unit.into())
             }
             Stmt::Block(statements) => {
-                let environment = Rc::new(RefCell::new(Environment::new(&env)));
-                for stmt in statements {
-                    match self.execute(stmt, environment.clone())? {
-                        StmtResult::Break => return Ok(StmtResult::Break),
-                        StmtResult::Continue => {
-                            if env.borrow_mut().is_enclosing_loop() {
-                                // This is to make sure the last block gets executed
-                                // in a desugared for loop
-                                return Ok(StmtResult::Continue);
-                            }
-                        }
-                        StmtResult::Value(_) => (),
-                    }
-                }
-                Ok(LoxValue::Unit.into())
+                self.depth += 1;
+                let result = self.execute_block(statements, &env);
+                self.depth -= 1;
+                result
             }
-            Stmt::If(condition, then_branch, else_branch) => {
-                if self
-                    .evaluate(&condition, &mut env.borrow_mut())?
-                    .is_truthy()
-                {
+            Stmt::If(keyword, condition, then_branch, else_branch) => {
+                let is_truthy = self.evaluate(condition, &env)?.is_truthy();
+                if let Some(coverage) = self.coverage.as_mut() {
+                    coverage.hit_branch(keyword.position, is_truthy);
+                }
+                if is_truthy {
                     self.execute(then_branch, env)
                 } else if let Some(else_branch) = else_branch {
                     self.execute(else_branch, env)
@@ -107,54 +305,241 @@ impl<'a> Interpreter<'a> {
                     Ok(LoxValue::Unit.into())
                 }
             }
-            Stmt::While(condition, body) => {
-                while self
-                    .evaluate(&condition, &mut env.borrow_mut())?
-                    .is_truthy()
-                {
-                    env.borrow_mut().is_loop = true;
-                    match self.execute(body, Rc::clone(&env))? {
-                        StmtResult::Break => break,
-                        StmtResult::Continue => continue,
-                        StmtResult::Value(_) => (),
-                    }
-                }
-                Ok(LoxValue::Unit.into())
+            Stmt::While(_, label, condition, body, increment) => {
+                self.execute_loop(label.as_ref(), condition, body, increment.as_ref(), &env)
             }
-            Stmt::Break(token) => {
-                if env.borrow_mut().is_inside_loop() {
-                    Ok(StmtResult::Break)
-                } else {
-                    Err(error(&token, "'break' must be inside a loop"))
+            Stmt::ForIn(keyword, label, variable, iterable, body) => {
+                let iterable = self.evaluate(iterable, &env)?;
+                if let Some(next) = iterable.next_function() {
+                    let next_item = |interpreter: &mut Self| interpreter.call_next(keyword, &next);
+                    return self.execute_for_in(label.as_ref(), variable, next_item, body, &env);
                 }
+                let Some(mut items) = iterable.items() else {
+                    return Err(error(
+                        keyword,
+                        "Can only iterate over strings, ranges, lists and maps",
+                    ));
+                };
+                let next_item = |_: &mut Self| Ok(items.next());
+                self.execute_for_in(label.as_ref(), variable, next_item, body, &env)
             }
-            Stmt::Continue(token) => {
-                if env.borrow_mut().is_inside_loop() {
-                    Ok(StmtResult::Continue)
-                } else {
-                    Err(error(&token, "'continue' must be inside a loop"))
+            // The parser checked that they are inside the loop they target
+            Stmt::Break(_, label, value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value, &env)?,
+                    None => LoxValue::Unit,
+                };
+                Ok(StmtResult::Break(label_name(label.as_ref()), value))
+            }
+            Stmt::Continue(_, label) => Ok(StmtResult::Continue(label_name(label.as_ref()))),
+        }
+    }
+
+    /// `break` and `continue` return from every statement up to the loop they target,
+    /// only `break` skips the increment
+    ///
+    /// The value of the loop is the value of the `break` stopping it, `()` when its condition
+    /// stops it.
+    fn execute_loop(
+        &mut self,
+        label: Option<&Token>,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<StmtResult> {
+        while self.evaluate(condition, env)?.is_truthy() {
+            let result = self.execute(body, Rc::clone(env))?;
+            if let Some(result) = loop_exit(label, result) {
+                return Ok(result);
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment, env)?;
+            }
+        }
+        Ok(LoxValue::Unit.into())
+    }
+
+    /// Each item is declared in a new environment, the iterations don't share their variable
+    fn execute_for_in(
+        &mut self,
+        label: Option<&Token>,
+        variable: &Token,
+        mut next_item: impl FnMut(&mut Self) -> LoxResult<Option<LoxValue>>,
+        body: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<StmtResult> {
+        while let Some(item) = next_item(self)? {
+            if let Some(tracer) = &self.tracer {
+                self.trace(tracer.declaration(variable, &item));
+            }
+            let environment = Rc::new(RefCell::new(Environment::new(env)));
+            environment.borrow_mut().declare(&variable.lexeme, item);
+            let result = self.execute(body, environment)?;
+            if let Some(result) = loop_exit(label, result) {
+                return Ok(result);
+            }
+        }
+        Ok(LoxValue::Unit.into())
+    }
+
+    /// The next item of an iterator, `None` when its `next` function returns nil
+    fn call_next(&mut self, keyword: &Token, next: &Function) -> LoxResult<Option<LoxValue>> {
+        if next.arity() != 0 {
+            return Err(error(
+                keyword,
+                &format!(
+                    "The next function of an iterator can't have arguments but has {}",
+                    next.arity()
+                ),
+            ));
+        }
+        match next.call(self, &[]) {
+            Ok(LoxValue::Nil) => Ok(None),
+            Ok(item) => Ok(Some(item)),
+            Err(message) => Err(error(keyword, &message)),
+        }
+    }
+
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<StmtResult> {
+        let environment = Rc::new(RefCell::new(Environment::new(env)));
+        // The value of a block is the value of its last statement
+        let mut last = LoxValue::Unit;
+        for stmt in statements {
+            match self.execute(stmt, environment.clone())? {
+                result @ (StmtResult::Break(..) | StmtResult::Continue(_)) => return Ok(result),
+                StmtResult::Value(value) => last = value,
+            }
+        }
+        Ok(last.into())
+    }
+
+    /// Lets the debugger pause before a statement
+    fn pause(&mut self, stmt: &Stmt, env: &Rc<RefCell<Environment>>) {
+        let Some(line) = statement_line(stmt) else {
+            return;
+        };
+        let Some(debugger) = self.debugger.as_mut() else {
+            return;
+        };
+        if !debugger.should_pause(line, self.depth) {
+            return;
+        }
+        let mut output = Vec::new();
+        debugger.paused(line, &mut output);
+        self.print_debugger_output(&mut output);
+        loop {
+            let command = self
+                .debugger
+                .as_mut()
+                .expect("debugger should be Some() while paused")
+                .read_command(line, self.depth, env, &mut output);
+            self.print_debugger_output(&mut output);
+            match command {
+                Command::Resume => return,
+                Command::Print(source) => {
+                    let value = self.evaluate_source(&source, env).ok();
+                    self.debugger
+                        .as_mut()
+                        .expect("debugger should be Some() while paused")
+                        .evaluated(value, &mut output);
+                    self.print_debugger_output(&mut output);
+                }
+                Command::Env => {
+                    let environment = env.borrow().to_string();
+                    self.logger
+                        .borrow_mut()
+                        .println(String::from(environment.trim_end()));
                 }
+                Command::Quit => std::process::exit(0),
             }
         }
     }
 
-    fn evaluate(&mut self, expr: &Expr, env: &mut Environment) -> LoxResult<LoxValue> {
+    /// Functions are named after the variable holding them, methods after their name
+    fn call_profiled(
+        &mut self,
+        callee: &Expr,
+        function: &Function,
+        args: &[LoxValue],
+    ) -> Result<LoxValue, String> {
+        let name = match callee {
+            Expr::Variable(token) | Expr::Get(_, token) => token.lexeme.as_str(),
+            _ => "<anonymous>",
+        };
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enter_function(name);
+        }
+        let value = function.call(self, args);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit();
+        }
+        value
+    }
+
+    fn trace(&self, message: Option<String>) {
+        if let Some(message) = message {
+            self.logger.borrow_mut().println(message);
+        }
+    }
+
+    fn print_debugger_output(&self, output: &mut Vec<String>) {
+        for message in output.drain(..) {
+            self.logger.borrow_mut().println(message);
+        }
+    }
+
+    /// Evaluates an expression typed in the debugger, errors are reported to the logger
+    fn evaluate_source(
+        &mut self,
+        source: &str,
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<LoxValue> {
+        let mut scanner = Scanner::new(self.logger, String::from(source));
+        scanner.is_compat = self.is_compat;
+        let tokens = scanner.scan_tokens().to_vec();
+        let expr = Parser::new(tokens, self.logger).parse_expression()?;
+        self.evaluate(&expr, env)
+            .inspect_err(|error| self.logger.borrow_mut().runtime_error(error))
+    }
+
+    fn evaluate(&mut self, expr: &Expr, env: &Rc<RefCell<Environment>>) -> LoxResult<LoxValue> {
+        let value = self.evaluate_expression(expr, env)?;
+        if let Some(tracer) = &self.tracer {
+            self.trace(tracer.expression(expr, &value));
+        }
+        Ok(value)
+    }
+
+    fn evaluate_expression(
+        &mut self,
+        expr: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<LoxValue> {
         match expr {
             Expr::Binary(left, operator, right) => {
                 self.evaluate_binary_op(left, operator, right, env)
             }
             Expr::Grouping(expr) => self.evaluate(expr, env),
+            Expr::Statement(stmt) => {
+                if let Some(coverage) = self.coverage.as_mut() {
+                    coverage.enter_expression();
+                }
+                match self.execute(stmt, Rc::clone(env))? {
+                    StmtResult::Value(value) => Ok(value),
+                    result => Err(LoxError::Control(result)),
+                }
+            }
             Expr::Literal(literal) => Ok(literal.clone().into()),
             Expr::Unary(operator, right) => self.evaluate_unary_op(operator, right, env),
-            Expr::Variable(token) => match env.get(&token)? {
-                LoxValue::Undefined => {
-                    Err(error(token, &format!("{} is undefined!", token.lexeme)))
-                }
-                value => Ok(value),
-            },
+            Expr::Variable(token) => env.borrow().get(token),
             Expr::Assign(token, value_expr) => {
-                let value = self.evaluate(&value_expr, env)?;
-                env.assign(token, value)
+                let value = self.evaluate(value_expr, env)?;
+                env.borrow_mut().assign(token, value)
             }
             Expr::Logical(left, operator, right) => {
                 let left = self.evaluate(left, env)?;
@@ -167,14 +552,14 @@ impl<'a> Interpreter<'a> {
                 }
                 self.evaluate(right, env)
             }
-            Expr::Call(callee, paren, args) => {
-                let callee = self.evaluate(callee, env)?;
+            Expr::Call(callee_expr, paren, args) => {
+                let callee = self.evaluate(callee_expr, env)?;
                 let args: LoxResult<Vec<LoxValue>> =
                     args.iter().map(|arg| self.evaluate(arg, env)).collect();
                 let args = args?;
                 match callee {
                     LoxValue::Function(function) => {
-                        if args.len() > function.arity() {
+                        if args.len() != function.arity() {
                             Err(error(
                                 paren,
                                 &format!(
@@ -183,23 +568,98 @@ impl<'a> Interpreter<'a> {
                                     args.len()
                                 ),
                             ))
+                        } else if self.profiler.is_some() {
+                            self.call_profiled(callee_expr, &function, &args)
+                                .map_err(|message| error(paren, &message))
                         } else {
-                            Ok(function.call(self, &args))
+                            function
+                                .call(self, &args)
+                                .map_err(|message| error(paren, &message))
                         }
                     }
                     _ => Err(error(paren, "Can only call functions and classes")),
                 }
             }
+            Expr::Get(object, name) => {
+                let object = self.evaluate(object, env)?;
+                if methods::arity(&object, &name.lexeme).is_none() {
+                    return Err(error(
+                        name,
+                        &format!("Undefined property '{}'", name.lexeme),
+                    ));
+                }
+                Ok(Function::Method(Box::new(object), name.lexeme.clone()).into())
+            }
+            Expr::Index(object, bracket, index) => {
+                let object = self.evaluate(object, env)?;
+                let index = self.evaluate(index, env)?;
+                index_value(&object, &index).map_err(|message| error(bracket, &message))
+            }
+            Expr::SetIndex(object, bracket, index, value) => {
+                self.evaluate_set_index(object, bracket, index, value, env)
+            }
+            Expr::List(_bracket, items) => {
+                let items: LoxResult<Vec<LoxValue>> =
+                    items.iter().map(|item| self.evaluate(item, env)).collect();
+                Ok(items?.into())
+            }
+            Expr::Map(brace, entries) => self.evaluate_map(brace, entries, env),
+            Expr::Range(start, operator, end) => {
+                let start = self.evaluate(start, env)?;
+                let end = self.evaluate(end, env)?;
+                let (LoxValue::Number(start), LoxValue::Number(end)) = (start, end) else {
+                    return Err(error(operator, "Range bounds must be numbers"));
+                };
+                let is_inclusive = operator.token_type == TokenType::DOT_DOT_EQUAL;
+                Range::new(start, end, is_inclusive)
+                    .map(LoxValue::Range)
+                    .map_err(|message| error(operator, &message))
+            }
+        }
+    }
+
+    fn evaluate_set_index(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<LoxValue> {
+        let target = self.evaluate(object, env)?;
+        let index = self.evaluate(index, env)?;
+        let value = self.evaluate(value, env)?;
+        set_index_value(&target, &index, value.clone())
+            .map_err(|message| error(bracket, &message))?;
+        if let Some(tracer) = &self.tracer {
+            self.trace(tracer.index_write(object, bracket, &index, &value));
+        }
+        Ok(value)
+    }
+
+    fn evaluate_map(
+        &mut self,
+        brace: &Token,
+        entries: &[(Expr, Expr)],
+        env: &Rc<RefCell<Environment>>,
+    ) -> LoxResult<LoxValue> {
+        let mut map = Map::default();
+        for (key, value) in entries {
+            let key = self.evaluate(key, env)?;
+            let key = Key::new(key).map_err(|message| error(brace, &message))?;
+            let value = self.evaluate(value, env)?;
+            map.insert(key, value);
         }
+        Ok(map.into())
     }
 
     fn evaluate_unary_op(
         &mut self,
         operator: &Token,
         right: &Expr,
-        env: &mut Environment,
+        env: &Rc<RefCell<Environment>>,
     ) -> LoxResult<LoxValue> {
-        let right = self.evaluate(&right, env)?;
+        let right = self.evaluate(right, env)?;
 
         match operator.token_type {
             TokenType::BANG => Ok(LoxValue::Boolean(!right.is_truthy())),
@@ -216,17 +676,17 @@ impl<'a> Interpreter<'a> {
         left: &Expr,
         operator: &Token,
         right: &Expr,
-        env: &mut Environment,
+        env: &Rc<RefCell<Environment>>,
     ) -> LoxResult<LoxValue> {
-        let left = self.evaluate(&left, env)?;
-        let right = self.evaluate(&right, env)?;
+        let left = self.evaluate(left, env)?;
+        let right = self.evaluate(right, env)?;
 
         match (&operator.token_type, (&left, &right)) {
             (TokenType::MINUS, (LoxValue::Number(left), LoxValue::Number(right))) => {
                 Ok(LoxValue::Number(left - right))
             }
             (TokenType::SLASH, (LoxValue::Number(left), LoxValue::Number(right))) => {
-                if right.approx_eq(0.0, F64Margin::default()) {
+                if !self.is_compat && right.approx_eq(0.0, F64Margin::default()) {
                     Err(error(operator, "Division by zero"))
                 } else {
                     Ok(LoxValue::Number(left / right))
@@ -239,10 +699,12 @@ impl<'a> Interpreter<'a> {
                 Ok(LoxValue::Number(left + right))
             }
             (TokenType::PLUS, (LoxValue::String(left), LoxValue::String(right))) => {
-                Ok(LoxValue::String(format!("{}{}", left, right)))
+                Ok(LoxValue::String(format!("{left}{right}")))
             }
-            (TokenType::PLUS, (LoxValue::String(left), LoxValue::Number(right))) => {
-                Ok(LoxValue::String(format!("{}{}", left, right)))
+            (TokenType::PLUS, (LoxValue::String(left), LoxValue::Number(right)))
+                if !self.is_compat =>
+            {
+                Ok(LoxValue::String(format!("{left}{right}")))
             }
             (TokenType::PLUS, _) => Err(error(
                 operator,
@@ -260,8 +722,8 @@ impl<'a> Interpreter<'a> {
             (TokenType::LESS_EQUAL, (LoxValue::Number(left), LoxValue::Number(right))) => {
                 Ok(LoxValue::Boolean(left <= right))
             }
-            (TokenType::BANG_EQUAL, _) => Ok(LoxValue::Boolean(!left.is_equal(right))),
-            (TokenType::EQUAL_EQUAL, _) => Ok(LoxValue::Boolean(left.is_equal(right))),
+            (TokenType::BANG_EQUAL, _) => Ok(LoxValue::Boolean(!left.is_equal(&right))),
+            (TokenType::EQUAL_EQUAL, _) => Ok(LoxValue::Boolean(left.is_equal(&right))),
             (_, _) => error_number_operand(operator),
         }
     }
@@ -1,16 +1,23 @@
 use crate::{
+    compat,
+    dap::Connection,
     lox::LoxError,
+    lsp::Diagnostic,
     token::{Position, Token, TokenType},
 };
 use enum_dispatch::enum_dispatch;
+use serde_json::json;
 use std::io::Stdout;
 use std::io::Write;
+use std::{cell::RefCell, rc::Rc};
 
-#[allow(clippy::module_name_repetitions)]
+#[allow(clippy::module_name_repetitions, clippy::enum_variant_names)]
 #[enum_dispatch(Logger)]
 pub enum LoggerImpl<'a> {
     DefaultLogger(DefaultLogger),
     TestLogger(TestLogger<'a>),
+    DapLogger(DapLogger),
+    DiagnosticLogger(DiagnosticLogger<'a>),
 }
 
 #[enum_dispatch]
@@ -18,6 +25,8 @@ pub trait Logger {
     fn println(&mut self, message: String);
     fn println_debug(&mut self, message: String);
     fn println_repl(&mut self, message: String);
+    /// Reports errors like jlox, see `compat`
+    fn is_compat(&self) -> bool;
 
     fn error(&mut self, token: &Token, message: String) {
         match token.token_type {
@@ -31,24 +40,41 @@ pub trait Logger {
         }
     }
 
-    fn runtime_error(&mut self, error: LoxError) {
+    fn runtime_error(&mut self, error: &LoxError) {
+        if self.is_compat() {
+            if let LoxError::Runtime(err) | LoxError::Panic(err) = error {
+                self.println(format!(
+                    "{}\n[line {}]",
+                    compat::jlox_message(&err.message),
+                    err.token.position.line
+                ));
+            }
+            return;
+        }
         match error {
             LoxError::Runtime(err) => {
-                self.report_error(err.token.position, "Runtime", "", err.message);
+                self.report_error(err.token.position, "Runtime", "", err.message.clone());
             }
             LoxError::Panic(err) => {
-                self.report_error(err.token.position, "Panic!", "", err.message);
-                std::process::exit(70)
+                self.report_error(err.token.position, "Panic!", "", err.message.clone());
             }
-            LoxError::Parser(_) => unreachable!(),
+            LoxError::Parser | LoxError::Control(_) => unreachable!(),
         }
     }
 
     fn report_error(&mut self, position: Position, tag: &str, error_where: &str, message: String) {
-        self.println(format!(
-            "[{}] {}Error {}: {}",
-            position, tag, error_where, message
-        ));
+        if self.is_compat() {
+            let separator = if error_where.is_empty() { "" } else { " " };
+            self.println(format!(
+                "[line {}] Error{}{}: {}",
+                position.line,
+                separator,
+                error_where,
+                compat::jlox_message(&message)
+            ));
+            return;
+        }
+        self.println(format!("[{position}] {tag}Error {error_where}: {message}"));
     }
 }
 
@@ -56,6 +82,7 @@ pub trait Logger {
 pub struct DefaultLogger {
     pub debug: bool,
     pub is_repl: bool,
+    pub is_compat: bool,
     output: Stdout,
 }
 
@@ -64,6 +91,7 @@ impl DefaultLogger {
         DefaultLogger {
             debug,
             is_repl,
+            is_compat: false,
             output: std::io::stdout(),
         }
     }
@@ -71,39 +99,131 @@ impl DefaultLogger {
 
 impl Logger for DefaultLogger {
     fn println(&mut self, message: String) {
-        writeln!(self.output, "{}", message).expect("Failed to write");
+        writeln!(self.output, "{message}").expect("Failed to write");
     }
 
     fn println_debug(&mut self, message: String) {
         if self.debug {
-            self.println(format!("DEBUG {}", message));
+            self.println(format!("DEBUG {message}"));
         }
     }
 
     fn println_repl(&mut self, message: String) {
         if self.is_repl {
-            self.println(format!("=> {}", message));
+            self.println(format!("=> {message}"));
         }
     }
+
+    fn is_compat(&self) -> bool {
+        self.is_compat
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub struct TestLogger<'a> {
     pub output: &'a mut Vec<u8>,
+    pub is_repl: bool,
+    pub is_compat: bool,
 }
 
 impl<'a> TestLogger<'a> {
-    #[allow(dead_code)]
     pub fn new(output: &'a mut Vec<u8>) -> Self {
-        TestLogger { output }
+        TestLogger {
+            output,
+            is_repl: false,
+            is_compat: false,
+        }
+    }
+}
+
+impl Logger for TestLogger<'_> {
+    fn println(&mut self, message: String) {
+        writeln!(self.output, "{message}").expect("Failed to write");
+    }
+
+    fn println_debug(&mut self, _message: String) {}
+
+    fn println_repl(&mut self, message: String) {
+        if self.is_repl {
+            self.println(format!("=> {message}"));
+        }
+    }
+
+    fn is_compat(&self) -> bool {
+        self.is_compat
+    }
+}
+
+/// Sends the output of the program as `output` events of the debug adapter protocol
+#[allow(clippy::module_name_repetitions)]
+pub struct DapLogger {
+    pub is_compat: bool,
+    connection: Rc<RefCell<Connection>>,
+}
+
+impl DapLogger {
+    pub fn new(connection: Rc<RefCell<Connection>>) -> Self {
+        DapLogger {
+            is_compat: false,
+            connection,
+        }
     }
 }
 
-impl<'a> Logger for TestLogger<'a> {
+impl Logger for DapLogger {
     fn println(&mut self, message: String) {
-        writeln!(self.output, "{}", message).expect("Failed to write");
+        self.connection.borrow_mut().event(
+            "output",
+            &json!({ "category": "stdout", "output": format!("{}\n", message) }),
+        );
     }
 
     fn println_debug(&mut self, _message: String) {}
+
     fn println_repl(&mut self, _message: String) {}
+
+    fn is_compat(&self) -> bool {
+        self.is_compat
+    }
+}
+
+/// Collects the errors as diagnostics for the language server, the rest of the output is dropped
+#[allow(clippy::module_name_repetitions)]
+pub struct DiagnosticLogger<'a> {
+    pub diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> DiagnosticLogger<'a> {
+    pub fn new(diagnostics: &'a mut Vec<Diagnostic>) -> Self {
+        DiagnosticLogger { diagnostics }
+    }
+}
+
+impl Logger for DiagnosticLogger<'_> {
+    fn println(&mut self, _message: String) {}
+
+    fn println_debug(&mut self, _message: String) {}
+
+    fn println_repl(&mut self, _message: String) {}
+
+    fn is_compat(&self) -> bool {
+        false
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.diagnostics.push(Diagnostic {
+            position: token.position,
+            length: token.lexeme.chars().count(),
+            message,
+        });
+    }
+
+    fn report_error(&mut self, position: Position, _tag: &str, _where: &str, message: String) {
+        // The scanner reports errors after the faulty character
+        self.diagnostics.push(Diagnostic {
+            position,
+            length: 1,
+            message,
+        });
+    }
 }
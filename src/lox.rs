@@ -1,10 +1,15 @@
 use crate::{
+    ast::{self, AstFormat, Stmt, StmtResult},
+    cst::{self, SyntaxToken},
+    formatter,
     function::Function,
     interpreter::Interpreter,
     logger::{Logger, LoggerImpl},
+    map::{Key, Map},
     parser::Parser,
+    range::Range,
     scanner::Scanner,
-    token::{Literal, Token},
+    token::{Literal, Token, TokenFormat},
 };
 
 use derive_new::new;
@@ -14,57 +19,114 @@ use std::{cell::RefCell, fmt, rc::Rc};
 pub struct Lox<'a> {
     pub logger: &'a Rc<RefCell<LoggerImpl<'a>>>,
     pub interpreter: Interpreter<'a>,
-    print_ast: bool,
     debug: bool,
+    /// Parses with the relaxed repl grammar, see `Parser::is_repl`
+    pub is_repl: bool,
+    is_compat: bool,
 }
 
 impl<'a> Lox<'a> {
-    pub fn new(logger: &'a Rc<RefCell<LoggerImpl<'a>>>, print_ast: bool, debug: bool) -> Self {
+    pub fn new(logger: &'a Rc<RefCell<LoggerImpl<'a>>>, debug: bool) -> Self {
         Lox {
             logger,
             interpreter: Interpreter::new(logger),
-            print_ast,
             debug,
+            is_repl: false,
+            is_compat: false,
         }
     }
 
+    /// Parses and runs the code like jlox, see `compat`
+    pub fn set_compat(&mut self, is_compat: bool) {
+        self.is_compat = is_compat;
+        self.interpreter.is_compat = is_compat;
+    }
+
+    /// Drops every variable declared in the session
+    pub fn reset(&mut self) {
+        self.interpreter = Interpreter::new(self.logger);
+        self.interpreter.is_compat = self.is_compat;
+    }
+
     pub fn run(&mut self, source: &str) -> LoxResult<()> {
+        let statements = self.parse(source)?;
+        self.interpreter.interpret(&statements)
+    }
+
+    /// Runs a program serialized with `ast::to_json`
+    pub fn run_json(&mut self, json: &str) -> serde_json::Result<LoxResult<()>> {
+        let statements = ast::from_json(json)?;
+        Ok(self.interpreter.interpret(&statements))
+    }
+
+    /// Parses the source and returns the ast in the requested format
+    pub fn ast(&mut self, source: &str, format: AstFormat) -> LoxResult<String> {
+        let statements = self.parse(source)?;
+        if self.debug {
+            self.logger
+                .borrow_mut()
+                .println_debug(format!("{:#?}", self.interpreter.environment));
+        }
+        Ok(format.format(&statements))
+    }
+
+    /// Scans the source and returns its tokens in the requested format, without parsing
+    pub fn tokens(&mut self, source: &str, format: TokenFormat) -> String {
         let mut scanner = Scanner::new(self.logger, String::from(source));
+        scanner.is_compat = self.is_compat;
+        format.format(scanner.scan_tokens())
+    }
+
+    /// Parses the source and returns its lossless concrete syntax tree
+    pub fn cst(&mut self, source: &str) -> LoxResult<String> {
+        let tokens = self.scan_lossless(source)?;
+        Ok(cst::build(tokens).to_string())
+    }
+
+    /// Formats the source to canonical lox, the source must parse without errors
+    pub fn format(&mut self, source: &str) -> LoxResult<String> {
+        let tokens = self.scan_lossless(source)?;
+        Ok(formatter::format(&tokens))
+    }
+
+    /// Scans the source with its trivia and makes sure it parses
+    fn scan_lossless(&mut self, source: &str) -> LoxResult<Vec<SyntaxToken>> {
+        let mut scanner = Scanner::new(self.logger, String::from(source));
+        scanner.is_compat = self.is_compat;
+        let tokens = scanner.scan_lossless();
+        let mut parser = Parser::new(
+            tokens.iter().map(|token| token.token.clone()).collect(),
+            self.logger,
+        );
+        parser.is_compat = self.is_compat;
+        parser.parse()?;
+        Ok(tokens)
+    }
+
+    fn parse(&mut self, source: &str) -> LoxResult<Vec<Stmt>> {
+        let mut scanner = Scanner::new(self.logger, String::from(source));
+        scanner.is_compat = self.is_compat;
         let tokens = scanner.scan_tokens();
         let mut parser = Parser::new(tokens.to_vec(), self.logger);
-        let statements = parser.parse()?;
-        if self.print_ast {
-            // TODO print to <file>.ast.lox
-            if self.debug {
-                self.logger
-                    .borrow_mut()
-                    .println(format!("{:#?}", self.interpreter.environment));
-            }
-            for statement in statements {
-                if self.debug {
-                    self.logger
-                        .borrow_mut()
-                        .println(format!("{:#?}", statement));
-                } else {
-                    self.logger.borrow_mut().println(format!("{}", statement));
-                }
-            }
-        } else {
-            self.interpreter.interpret(&statements);
-        }
-        Ok(())
+        parser.is_repl = self.is_repl;
+        parser.is_compat = self.is_compat;
+        parser.parse()
     }
 }
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug, Clone)]
 pub enum LoxValue {
-    Nil,       // TODO implement Option<T> and remove nil
-    Undefined, // This is used as a flag, there are no corresponding literal
+    Nil, // TODO implement Option<T> and remove nil
     Number(f64),
     Boolean(bool),
     String(String),
     Function(Function),
+    Range(Range),
+    /// Lists are shared, a list changed through a variable is changed everywhere
+    List(Rc<RefCell<Vec<LoxValue>>>),
+    /// Shared like lists
+    Map(Rc<RefCell<Map>>),
     Unit,
 }
 
@@ -111,37 +173,188 @@ impl From<Function> for LoxValue {
 }
 
 impl LoxValue {
+    /// The items a `for in` loop goes through, `None` if the value can't be iterated
+    ///
+    /// The items are taken when the loop starts, changing the value in the loop
+    /// doesn't change them. Iterators give their items one at a time, see `next_function`.
+    pub fn items(&self) -> Option<Box<dyn Iterator<Item = LoxValue>>> {
+        match self {
+            LoxValue::String(value) => {
+                let chars: Vec<LoxValue> = value
+                    .chars()
+                    .map(|c| LoxValue::from(c.to_string()))
+                    .collect();
+                Some(Box::new(chars.into_iter()))
+            }
+            LoxValue::Range(range) => Some(Box::new(range.iter().map(LoxValue::from))),
+            LoxValue::List(list) => Some(Box::new(list.borrow().clone().into_iter())),
+            // The entries are `[key, value]` lists
+            LoxValue::Map(map) => {
+                let entries: Vec<LoxValue> = map
+                    .borrow()
+                    .entries()
+                    .map(|(key, value)| LoxValue::from(vec![key.clone(), value.clone()]))
+                    .collect();
+                Some(Box::new(entries.into_iter()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The `next` function of an iterator, a map like `{"next": stack.pop}`
+    ///
+    /// A `for in` loop over an iterator calls `next` before each iteration
+    /// and stops when it returns nil.
+    pub fn next_function(&self) -> Option<Function> {
+        let LoxValue::Map(map) = self else {
+            return None;
+        };
+        let key = Key::new(LoxValue::from(String::from("next"))).ok()?;
+        match map.borrow().get(&key) {
+            Some(LoxValue::Function(function)) => Some(function.clone()),
+            _ => None,
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
-            LoxValue::Nil | LoxValue::Undefined => false,
+            LoxValue::Nil => false,
             LoxValue::Boolean(value) => *value,
             _ => true,
         }
     }
 
-    pub fn is_equal(&self, other: LoxValue) -> bool {
+    pub fn is_equal(&self, other: &LoxValue) -> bool {
+        self.is_equal_in(other, &mut Vec::new())
+    }
+
+    fn is_equal_in(&self, other: &LoxValue, compared: &mut Vec<(*const (), *const ())>) -> bool {
         match (self, other) {
             (LoxValue::Nil, LoxValue::Nil) => true,
-            (LoxValue::Number(a), LoxValue::Number(b)) => a.approx_eq(b, F64Margin::default()),
-            (LoxValue::String(ref a), LoxValue::String(ref b)) => *a == *b,
-            (LoxValue::Boolean(a), LoxValue::Boolean(b)) => *a == b,
+            // NaN is never equal to itself, approx_eq only compares the bits
+            (LoxValue::Number(a), LoxValue::Number(b)) => {
+                !a.is_nan() && a.approx_eq(*b, F64Margin::default())
+            }
+            (LoxValue::String(a), LoxValue::String(b)) => a == b,
+            (LoxValue::Boolean(a), LoxValue::Boolean(b)) => a == b,
+            (LoxValue::Range(a), LoxValue::Range(b)) => a == b,
+            (LoxValue::List(a), LoxValue::List(b)) => compare_once(a, b, compared, |compared| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.is_equal_in(b, compared))
+            }),
+            // The order of the entries doesn't matter
+            (LoxValue::Map(a), LoxValue::Map(b)) => compare_once(a, b, compared, |compared| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.entries().all(|(key, value)| {
+                        Key::new(key.clone())
+                            .ok()
+                            .and_then(|key| b.get(&key))
+                            .is_some_and(|other| value.is_equal_in(other, compared))
+                    })
+            }),
             _ => false, // no type coercion
         }
     }
 }
 
+/// Compares two lists or two maps with `is_equal`, `compared` has the pairs being compared
+///
+/// A pair compared again is in a cycle, like `a` in `a.push(a)`, it is equal unless
+/// the other items of the cycle differ.
+fn compare_once<T>(
+    a: &Rc<T>,
+    b: &Rc<T>,
+    compared: &mut Vec<(*const (), *const ())>,
+    is_equal: impl FnOnce(&mut Vec<(*const (), *const ())>) -> bool,
+) -> bool {
+    let pair = (Rc::as_ptr(a).cast::<()>(), Rc::as_ptr(b).cast::<()>());
+    if Rc::ptr_eq(a, b) || compared.contains(&pair) {
+        return true;
+    }
+    compared.push(pair);
+    let is_equal = is_equal(compared);
+    compared.pop();
+    is_equal
+}
+
 impl fmt::Display for LoxValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, &mut Vec::new())
+    }
+}
+
+impl LoxValue {
+    /// `printing` has the lists and maps being printed, one printed again is in a cycle
+    /// and is printed as `[...]` or `{...}`
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
         match self {
             LoxValue::Nil => write!(f, "nil"),
-            LoxValue::Undefined => write!(f, "undefined"),
-            LoxValue::Number(value) => write!(f, "{}", value),
-            LoxValue::Boolean(value) => write!(f, "{}", value),
-            LoxValue::String(value) => write!(f, "{}", value),
-            LoxValue::Function(function) => function.fmt(f),
+            LoxValue::Number(value) => write!(f, "{value}"),
+            LoxValue::Boolean(value) => write!(f, "{value}"),
+            LoxValue::String(value) => write!(f, "{value}"),
+            LoxValue::Function(function) => write!(f, "{function}"),
+            LoxValue::Range(range) => write!(f, "{range}"),
+            LoxValue::List(list) => {
+                let pointer = Rc::as_ptr(list).cast::<()>();
+                if printing.contains(&pointer) {
+                    return write!(f, "[...]");
+                }
+                printing.push(pointer);
+                write!(f, "[")?;
+                for (i, item) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_item(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "]")
+            }
+            LoxValue::Map(map) => {
+                let pointer = Rc::as_ptr(map).cast::<()>();
+                if printing.contains(&pointer) {
+                    return write!(f, "{{...}}");
+                }
+                printing.push(pointer);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().entries().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_item(f, printing)?;
+                    write!(f, ": ")?;
+                    value.fmt_item(f, printing)?;
+                }
+                printing.pop();
+                write!(f, "}}")
+            }
             LoxValue::Unit => write!(f, "()"),
         }
     }
+
+    /// Strings are quoted in lists and maps, `["a", "b"]` doesn't look like `["a, b"]`
+    fn fmt_item(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            LoxValue::String(value) => write!(f, "\"{value}\""),
+            _ => self.fmt_in(f, printing),
+        }
+    }
+}
+
+impl From<Vec<LoxValue>> for LoxValue {
+    fn from(items: Vec<LoxValue>) -> Self {
+        LoxValue::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl From<Map> for LoxValue {
+    fn from(map: Map) -> Self {
+        LoxValue::Map(Rc::new(RefCell::new(map)))
+    }
 }
 
 #[derive(new)]
@@ -152,9 +365,24 @@ pub struct ErrorData {
 
 #[allow(clippy::module_name_repetitions)]
 pub enum LoxError {
-    Parser(ErrorData),
+    /// The parser reports its errors to the logger when it finds them
+    Parser,
     Runtime(ErrorData),
     Panic(ErrorData),
+    /// A `break` or `continue` leaving the expression it is in, the interpreter turns it back
+    /// into a `StmtResult` at the end of the statement of the expression
+    Control(StmtResult),
+}
+
+impl LoxError {
+    /// The exit code of a script stopped by this error, the sysexits codes used by jlox
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Parser => 65,
+            LoxError::Runtime(_) | LoxError::Panic(_) => 70,
+            LoxError::Control(_) => 0,
+        }
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -0,0 +1,471 @@
+use crate::{
+    ast::{Expr, Stmt},
+    highlight::{self, Highlight},
+    logger::{DiagnosticLogger, LoggerImpl},
+    parser::Parser,
+    scanner::Scanner,
+    token::{self, Position, Token, TokenType},
+    transport::Transport,
+};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    convert::TryFrom,
+    rc::Rc,
+};
+
+// A Language Server Protocol server, the protocol used by VS Code for diagnostics,
+// go to definition, hover and completion. Messages are json-rpc objects exchanged
+// over stdio, see `transport`.
+//
+// Documents are scanned and parsed again after every change. The statements that
+// parse are walked like the interpreter would run them to link every variable
+// to the `let` declaring it.
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Kinds from the protocol
+const SEVERITY_ERROR: u64 = 1;
+const SYMBOL_VARIABLE: u64 = 13;
+const COMPLETION_VARIABLE: u64 = 6;
+const COMPLETION_KEYWORD: u64 = 14;
+
+/// An error reported while scanning or parsing, `position` is the end of the faulty token
+pub struct Diagnostic {
+    pub position: Position,
+    pub length: usize,
+    pub message: String,
+}
+
+/// Links the variables to their declaration, with the scoping rules of the interpreter
+#[derive(Default)]
+struct Resolver {
+    scopes: Vec<HashMap<String, usize>>,
+    /// The `let` declarations and the `for` variables, in the order of the source
+    declarations: Vec<Token>,
+    /// Every use of a variable, the declarations included, with the index of its declaration,
+    /// `None` for natives like `clock` and undeclared variables
+    references: Vec<(Token, Option<usize>)>,
+}
+
+impl Resolver {
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) | Stmt::Print(_, expr) => self.expression(expr),
+            Stmt::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.scopes.pop();
+            }
+            Stmt::Let(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.declare(name);
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While(_, _, condition, body, increment) => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+            }
+            Stmt::ForIn(_, _, variable, iterable, body) => {
+                self.expression(iterable);
+                // The variable is declared in its own scope, around the body
+                self.scopes.push(HashMap::new());
+                self.declare(variable);
+                self.statement(body);
+                self.scopes.pop();
+            }
+            Stmt::Break(_, _, value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Continue(..) => (),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary(left, _, right)
+            | Expr::Logical(left, _, right)
+            | Expr::Range(left, _, right)
+            | Expr::Index(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expr::Grouping(expr) | Expr::Unary(_, expr) | Expr::Get(expr, _) => {
+                self.expression(expr);
+            }
+            Expr::Statement(stmt) => self.statement(stmt),
+            Expr::Literal(_) => (),
+            Expr::Variable(name) => self.resolve(name),
+            Expr::Assign(name, value) => {
+                self.expression(value);
+                self.resolve(name);
+            }
+            Expr::Call(callee, _, args) => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expr::SetIndex(object, _, index, value) => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expr::List(_, items) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+            Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let index = self.declarations.len();
+        self.declarations.push(name.clone());
+        self.references.push((name.clone(), Some(index)));
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), index);
+        }
+    }
+
+    fn resolve(&mut self, name: &Token) {
+        let index = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme).copied());
+        self.references.push((name.clone(), index));
+    }
+}
+
+/// What the server knows about an open document
+struct Document {
+    lines: Vec<String>,
+    tokens: Vec<Token>,
+    diagnostics: Vec<Diagnostic>,
+    resolver: Resolver,
+}
+
+impl Document {
+    fn new(source: &str) -> Self {
+        let mut diagnostics = Vec::new();
+        let (tokens, statements) = {
+            let logger = DiagnosticLogger::new(&mut diagnostics);
+            let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
+            let tokens = Scanner::new(&logger, String::from(source))
+                .scan_tokens()
+                .to_vec();
+            let statements = Parser::new(tokens.clone(), &logger).parse_lenient();
+            (tokens, statements)
+        };
+        let mut resolver = Resolver {
+            scopes: vec![HashMap::new()],
+            ..Resolver::default()
+        };
+        for statement in &statements {
+            resolver.statement(statement);
+        }
+        Document {
+            lines: source.lines().map(String::from).collect(),
+            tokens,
+            diagnostics,
+            resolver,
+        }
+    }
+
+    /// The lsp character of a column of the scanner on this line, starting at 0
+    ///
+    /// The scanner counts columns in chars, lsp characters are UTF-16 code units.
+    fn character(&self, line: usize, column: usize) -> usize {
+        let text = self.lines.get(line).map_or("", String::as_str);
+        let units: usize = text.chars().take(column).map(char::len_utf16).sum();
+        units + column.saturating_sub(text.chars().count())
+    }
+
+    /// The lsp start and end characters of a token on its line
+    fn characters(&self, position: Position, length: usize) -> (usize, usize) {
+        let line = position.line - 1;
+        let end = position.column - 1;
+        (
+            self.character(line, end.saturating_sub(length)),
+            self.character(line, end),
+        )
+    }
+
+    /// The range of a token, lsp positions start at 0 and point at the start of a character
+    fn range(&self, position: Position, length: usize) -> Value {
+        let line = position.line - 1;
+        let (start, end) = self.characters(position, length);
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+
+    fn token_range(&self, token: &Token) -> Value {
+        self.range(token.position, token.lexeme.chars().count())
+    }
+
+    fn contains(&self, token: &Token, line: usize, character: usize) -> bool {
+        let (start, end) = self.characters(token.position, token.lexeme.chars().count());
+        token.position.line - 1 == line && start <= character && character <= end
+    }
+
+    fn diagnostics(&self) -> Value {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| {
+                json!({
+                    "range": self.range(diagnostic.position, diagnostic.length),
+                    "severity": SEVERITY_ERROR,
+                    "source": "lox",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect()
+    }
+
+    /// The declaration of the variable at this position
+    fn declaration_at(&self, line: usize, character: usize) -> Option<&Token> {
+        let (_, index) = self
+            .resolver
+            .references
+            .iter()
+            .find(|(token, _)| self.contains(token, line, character))?;
+        self.resolver.declarations.get((*index)?)
+    }
+
+    /// Shows the line of the declaration, it has the initial value of the variable
+    fn hover(&self, declaration: &Token) -> Value {
+        let line = self
+            .lines
+            .get(declaration.position.line - 1)
+            .map_or("", |line| line.trim());
+        json!({
+            "contents": { "kind": "markdown", "value": format!("```lox\n{}\n```", line) },
+        })
+    }
+
+    fn symbols(&self) -> Value {
+        self.resolver
+            .declarations
+            .iter()
+            .map(|declaration| {
+                json!({
+                    "name": declaration.lexeme,
+                    "kind": SYMBOL_VARIABLE,
+                    "range": self.token_range(declaration),
+                    "selectionRange": self.token_range(declaration),
+                })
+            })
+            .collect()
+    }
+
+    /// The keywords and every identifier of the document
+    ///
+    /// Identifiers come from the tokens, they are known even while the document doesn't parse.
+    fn completion(&self) -> Value {
+        let identifiers: BTreeSet<&str> = self
+            .tokens
+            .iter()
+            .filter(|token| token.token_type == TokenType::IDENTIFIER)
+            .map(|token| token.lexeme.as_str())
+            .collect();
+        let keywords = token::KEYWORDS
+            .iter()
+            .map(|(keyword, _)| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }));
+        let variables = identifiers
+            .into_iter()
+            .map(|identifier| json!({ "label": identifier, "kind": COMPLETION_VARIABLE }));
+        keywords.chain(variables).collect()
+    }
+}
+
+impl Document {
+    /// Each token is 5 numbers: its line and start relative to the previous token,
+    /// its length, its type in `highlight::LEGEND` and its modifiers
+    ///
+    /// Multi-line strings are left out, tokens have to fit on a line.
+    fn semantic_tokens(&self) -> Value {
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for token in &self.tokens {
+            let Some(token_type) = Highlight::of(token.token_type).semantic_type() else {
+                continue;
+            };
+            if token.lexeme.contains('\n') {
+                continue;
+            }
+            let line = token.position.line - 1;
+            let (start, end) = self.characters(token.position, token.lexeme.chars().count());
+            let length = end - start;
+            if line != previous_line {
+                previous_start = 0;
+            }
+            data.extend([
+                line - previous_line,
+                start - previous_start,
+                length,
+                token_type,
+                0,
+            ]);
+            previous_line = line;
+            previous_start = start;
+        }
+        json!({ "data": data })
+    }
+}
+
+pub struct Server {
+    transport: Transport,
+    documents: HashMap<String, Document>,
+}
+
+impl Server {
+    pub fn new(transport: Transport) -> Self {
+        Server {
+            transport,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Answers requests until the client sends `exit` or closes the input
+    pub fn run(&mut self) {
+        loop {
+            let Some(message) = self.transport.read() else {
+                return;
+            };
+            let method = message["method"].as_str().unwrap_or("");
+            if method == "exit" {
+                return;
+            }
+            let result = self.handle(method, &message["params"]);
+            // Notifications don't have an id and never get a response
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+            let response = match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("Unsupported method {}", method) },
+                }),
+            };
+            self.transport.write(&response);
+        }
+    }
+
+    /// The result of a request, `None` if the method is not supported
+    fn handle(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let line = as_usize(&params["position"]["line"]);
+        let character = as_usize(&params["position"]["character"]);
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": highlight::LEGEND, "tokenModifiers": [] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "lox_rs" },
+            }),
+            "initialized" | "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.open(uri, text);
+                Value::Null
+            }
+            // The server only asks for full documents, the last change has the whole text
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let text = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                    .unwrap_or("");
+                self.open(uri, text);
+                Value::Null
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri, &json!([]));
+                Value::Null
+            }
+            "textDocument/definition" => self.documents.get(uri).map_or(Value::Null, |document| {
+                document.declaration_at(line, character).map_or(
+                    Value::Null,
+                    |declaration| json!({ "uri": uri, "range": document.token_range(declaration) }),
+                )
+            }),
+            "textDocument/hover" => self.documents.get(uri).map_or(Value::Null, |document| {
+                document
+                    .declaration_at(line, character)
+                    .map_or(Value::Null, |declaration| document.hover(declaration))
+            }),
+            "textDocument/documentSymbol" => self
+                .documents
+                .get(uri)
+                .map_or(Value::Null, Document::symbols),
+            "textDocument/completion" => self
+                .documents
+                .get(uri)
+                .map_or(Value::Null, Document::completion),
+            "textDocument/semanticTokens/full" => self
+                .documents
+                .get(uri)
+                .map_or(Value::Null, Document::semantic_tokens),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn open(&mut self, uri: &str, text: &str) {
+        let document = Document::new(text);
+        let diagnostics = document.diagnostics();
+        self.documents.insert(String::from(uri), document);
+        self.publish_diagnostics(uri, &diagnostics);
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: &Value) {
+        self.transport.write(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+}
+
+fn as_usize(value: &Value) -> usize {
+    value
+        .as_u64()
+        .and_then(|value| usize::try_from(value).ok())
+        .unwrap_or(0)
+}
@@ -2,14 +2,31 @@
 #![warn(clippy::pedantic)]
 
 mod ast;
+mod compat;
+mod conformance;
+mod coverage;
+mod cst;
+mod dap;
+mod debugger;
 mod environment;
+mod formatter;
 mod function;
+mod golden;
+mod highlight;
 mod interpreter;
 mod logger;
 mod lox;
+mod lsp;
+mod map;
+mod methods;
 mod parser;
+mod profiler;
+mod range;
+mod repl;
 mod scanner;
 mod token;
+mod tracer;
+mod transport;
 
 #[cfg(test)]
 mod tests;
@@ -17,18 +34,32 @@ mod tests;
 use std::{
     cell::RefCell,
     fs,
-    io::{self, prelude::*},
+    io::{self, Write},
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use structopt::StructOpt;
 
-use logger::{DefaultLogger, LoggerImpl};
-use lox::{Lox, LoxError};
+use ast::AstFormat;
+use coverage::Coverage;
+use dap::Connection;
+use debugger::{Breakpoint, Debugger, Frontend};
+use logger::{DefaultLogger, Logger, LoggerImpl};
+use lox::Lox;
+use profiler::Profiler;
+use repl::{Command as ReplCommand, Repl};
+use token::TokenFormat;
+use tracer::Tracer;
+use transport::Transport;
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, StructOpt)]
 #[structopt(name = "loxrs", about = "A rust implementation of a lox interpreter")]
 pub struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// Input file
     input: Option<String>,
 
@@ -36,50 +67,335 @@ pub struct Opt {
     #[structopt(short, long)]
     debug: bool,
 
-    /// Print ast to <file>.ast.lox
+    /// Print ast to <file>.ast.lox, or <file>.ast.json with --ast-format json
     #[structopt(long)]
     ast: bool,
+
+    /// Print ast to this path instead of <file>.ast.lox, implies --ast, in the repl the ast of every line is added to it
+    #[structopt(long, parse(from_os_str))]
+    ast_out: Option<PathBuf>,
+
+    /// Format of the printed ast: sexpr, debug or json
+    #[structopt(long, default_value = "sexpr", possible_values = &["sexpr", "debug", "json"])]
+    ast_format: AstFormat,
+
+    /// Print the tokens of the input and exit without parsing
+    #[structopt(long)]
+    tokens: bool,
+
+    /// Format of the printed tokens: text or json
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    tokens_format: TokenFormat,
+
+    /// Print the lossless concrete syntax tree, with whitespace and comments
+    #[structopt(long)]
+    cst: bool,
+
+    /// Input file is a json ast, as printed by --ast-format json
+    #[structopt(long)]
+    from_json: bool,
+
+    /// Parse, run and report errors like jlox, the reference implementation
+    #[structopt(long)]
+    compat: bool,
+
+    /// Run the input in a step debugger, type help at the prompt for its commands
+    #[structopt(long)]
+    debug_interactive: bool,
+
+    /// Breakpoint for --debug-interactive, as `file:line` or `line`, can be repeated
+    #[structopt(long = "break", number_of_values = 1)]
+    breakpoints: Vec<String>,
+
+    /// Time every line and function of the input, then print a report sorted by self time
+    #[structopt(long)]
+    profile: bool,
+
+    /// Write the profiled stacks to this file in the folded format of flamegraph tools, implies --profile
+    #[structopt(long, parse(from_os_str))]
+    profile_folded: Option<PathBuf>,
+
+    /// Write the lines and the if branches that ran to <file>.lcov, in the lcov format
+    #[structopt(long)]
+    coverage: bool,
+
+    /// Write the coverage to this file instead, implies --coverage
+    #[structopt(long, parse(from_os_str))]
+    coverage_out: Option<PathBuf>,
+
+    /// Print every statement run, the value of every expression and every variable read and write
+    #[structopt(long)]
+    trace: bool,
+
+    /// Only trace these lines, as `start-end` or `line`, implies --trace
+    #[structopt(long)]
+    trace_lines: Option<String>,
+
+    /// Only trace the reads and writes of this variable, can be repeated, implies --trace
+    #[structopt(long = "trace-var", number_of_values = 1)]
+    trace_variables: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Format lox files in place
+    Fmt {
+        /// Only check the formatting, exits with 1 if a file is not formatted
+        #[structopt(long)]
+        check: bool,
+
+        /// Files to format
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Run golden files, lox scripts annotated with their expected output
+    Test {
+        /// Files or directories of .lox files
+        #[structopt(parse(from_os_str), default_value = "tests/lox")]
+        paths: Vec<PathBuf>,
+    },
+    /// Run the Crafting Interpreters test suite in compat mode and report the passing chapters
+    Conformance {
+        /// Print the diff of every failing file
+        #[structopt(short, long)]
+        verbose: bool,
+
+        /// The test directory of the upstream repository, see tests/craftinginterpreters/README.md
+        #[structopt(parse(from_os_str), default_value = "tests/craftinginterpreters")]
+        path: PathBuf,
+    },
+    /// Serve the Debug Adapter Protocol over stdio, used by the VS Code extension in editors/vscode
+    Dap,
+    /// Serve the Language Server Protocol over stdio, used by the VS Code extension in editors/vscode
+    Lsp,
+    /// Print the textmate grammar generated from the token types, used by editors/vscode
+    Grammar {
+        /// Write the grammar to this file instead
+        #[structopt(parse(from_os_str))]
+        out: Option<PathBuf>,
+    },
+}
+
+impl Opt {
+    fn print_ast(&self) -> bool {
+        self.ast || self.ast_out.is_some()
+    }
 }
 
 fn main() -> io::Result<()> {
     let opt = Opt::from_args();
 
     let mut logger = DefaultLogger::new(opt.debug, false);
-    if opt.input.is_some() {
-        run_file(logger, opt)
+    logger.is_compat = opt.compat;
+    if let Some(Command::Fmt { check, files }) = &opt.command {
+        run_fmt(logger, files, *check)
+    } else if let Some(Command::Test { paths }) = &opt.command {
+        run_test(paths, opt.compat)
+    } else if let Some(Command::Conformance { verbose, path }) = &opt.command {
+        run_conformance(path, *verbose)
+    } else if let Some(Command::Dap) = &opt.command {
+        let connection = Connection::new(stdio_transport());
+        dap::run(&Rc::new(RefCell::new(connection)), opt.compat);
+        Ok(())
+    } else if let Some(Command::Grammar { out }) = &opt.command {
+        let grammar = highlight::grammar_json();
+        if let Some(path) = out {
+            fs::write(path, grammar)
+        } else {
+            print!("{grammar}");
+            Ok(())
+        }
+    } else if let Some(Command::Lsp) = &opt.command {
+        lsp::Server::new(stdio_transport()).run();
+        Ok(())
+    } else if opt.input.is_some() {
+        run_file(logger, &opt)
     } else {
         logger.is_repl = true;
         run_prompt(logger, &opt)
     }
 }
 
-fn run_file(logger: DefaultLogger, opt: Opt) -> io::Result<()> {
+fn stdio_transport() -> Transport {
+    Transport::new(
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    )
+}
+
+fn run_file(logger: DefaultLogger, opt: &Opt) -> io::Result<()> {
     let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
-    let mut lox = Lox::new(&logger, opt.ast, opt.debug);
-    let source = fs::read_to_string(opt.input.unwrap()).expect("Failed to read file");
-    let result = lox.run(&source);
+    let mut lox = Lox::new(&logger, opt.debug);
+    lox.set_compat(opt.compat);
+    let input = opt
+        .input
+        .as_ref()
+        .expect("input should be Some() in file mode");
+    let source = fs::read_to_string(input).expect("Failed to read file");
+    if opt.debug_interactive {
+        let file = PathBuf::from(input);
+        let mut breakpoints = Vec::new();
+        for text in &opt.breakpoints {
+            let Some(breakpoint) = Breakpoint::parse(text, &file) else {
+                println!("Invalid breakpoint \"{text}\", expected [file:]line");
+                std::process::exit(64)
+            };
+            breakpoints.push(breakpoint);
+        }
+        let stdin = Frontend::Console(Box::new(io::BufReader::new(io::stdin())));
+        lox.interpreter.debugger = Some(Debugger::new(file, &source, breakpoints, stdin));
+    }
+    if opt.profile || opt.profile_folded.is_some() {
+        let name = Path::new(input)
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        lox.interpreter.profiler = Some(Profiler::new(&name, &source));
+    }
+    if opt.trace || opt.trace_lines.is_some() || !opt.trace_variables.is_empty() {
+        let lines = opt.trace_lines.as_ref().map(|text| {
+            Tracer::parse_lines(text).unwrap_or_else(|| {
+                println!("Invalid lines \"{text}\", expected start-end or line");
+                std::process::exit(64)
+            })
+        });
+        let tracer = Tracer {
+            lines,
+            variables: opt.trace_variables.clone(),
+        };
+        lox.interpreter.tracer = Some(tracer);
+    }
+    if opt.coverage || opt.coverage_out.is_some() {
+        lox.interpreter.coverage = Some(Coverage::new(PathBuf::from(input)));
+    }
+    let result = if opt.tokens {
+        println!("{}", lox.tokens(&source, opt.tokens_format));
+        Ok(())
+    } else if opt.print_ast() {
+        let path = opt
+            .ast_out
+            .clone()
+            .unwrap_or_else(|| Path::new(input).with_extension(opt.ast_format.extension()));
+        lox.ast(&source, opt.ast_format)
+            .map(|ast| fs::write(path, ast).expect("Failed to write ast"))
+    } else if opt.cst {
+        lox.cst(&source).map(|cst| print!("{cst}"))
+    } else if opt.from_json {
+        lox.run_json(&source).unwrap_or_else(|error| {
+            logger
+                .borrow_mut()
+                .println(format!("Invalid json ast: {error}"));
+            std::process::exit(65)
+        })
+    } else {
+        lox.run(&source)
+    };
+    if let Some(profiler) = lox.interpreter.profiler.take() {
+        logger
+            .borrow_mut()
+            .println(String::from(profiler.to_string().trim_end()));
+        if let Some(path) = &opt.profile_folded {
+            fs::write(path, profiler.folded())?;
+        }
+    }
+    if let Some(coverage) = lox.interpreter.coverage.take() {
+        let path = opt
+            .coverage_out
+            .clone()
+            .unwrap_or_else(|| Path::new(input).with_extension("lcov"));
+        fs::write(path, coverage.to_string())?;
+    }
     match result {
-        Err(LoxError::Parser(_)) => std::process::exit(65),
-        Err(LoxError::Panic(_)) => std::process::exit(70),
-        _ => Ok(()),
+        Err(error) => std::process::exit(error.exit_code()),
+        Ok(()) => Ok(()),
     }
 }
 
-fn run_prompt(logger: DefaultLogger, opt: &Opt) -> io::Result<()> {
+fn run_fmt(logger: DefaultLogger, files: &[PathBuf], check: bool) -> io::Result<()> {
     let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
-    let mut lox = Lox::new(&logger, opt.ast, opt.debug);
-    println!("lox prompt: ");
-    loop {
-        print!("> ");
-        io::stdout().flush()?;
+    let mut lox = Lox::new(&logger, false);
+    let mut exit_code = 0;
+    for file in files {
+        let source = fs::read_to_string(file)?;
+        match lox.format(&source) {
+            Ok(formatted) if formatted == source => (),
+            Ok(_) if check => {
+                println!("{} is not formatted", file.display());
+                exit_code = 1;
+            }
+            Ok(formatted) => fs::write(file, formatted)?,
+            Err(_) => {
+                println!("Could not format {}", file.display());
+                exit_code = 65;
+            }
+        }
+    }
+    std::process::exit(exit_code)
+}
 
-        let mut buffer = String::new();
-        io::stdin().read_line(&mut buffer)?;
+fn run_test(paths: &[PathBuf], is_compat: bool) -> io::Result<()> {
+    let files = golden::collect(paths)?;
+    let mut failed = 0;
+    for file in &files {
+        match golden::run_file(file, is_compat)? {
+            golden::Outcome::Pass => println!("PASS {}", file.display()),
+            golden::Outcome::Fail(diff) => {
+                println!("FAIL {}\n{}", file.display(), diff);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failed, failed);
+    std::process::exit(i32::from(failed > 0))
+}
 
-        if buffer.trim() == "exit" {
-            std::process::exit(0);
+fn run_conformance(path: &Path, verbose: bool) -> io::Result<()> {
+    let chapters = conformance::run(path)?;
+    if chapters.iter().all(|chapter| chapter.total == 0) {
+        println!(
+            "No tests found in {}, see tests/craftinginterpreters/README.md",
+            path.display()
+        );
+        std::process::exit(66)
+    }
+    for chapter in &chapters {
+        println!("{chapter}");
+        if verbose {
+            for (file, diff) in &chapter.failures {
+                println!("FAIL {}\n{}", file.display(), diff);
+            }
         }
+    }
+    let failed = chapters.iter().any(|chapter| !chapter.failures.is_empty());
+    std::process::exit(i32::from(failed))
+}
 
-        lox.run(&buffer).ok();
+fn run_prompt(logger: DefaultLogger, opt: &Opt) -> io::Result<()> {
+    let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
+    let mut lox = Lox::new(&logger, opt.debug);
+    lox.set_compat(opt.compat);
+    lox.is_repl = true;
+    let mut ast_out = opt.ast_out.as_ref().map(fs::File::create).transpose()?;
+    let mut repl = Repl::new(lox.interpreter.environment.clone()).map_err(io::Error::other)?;
+    println!("lox prompt: ");
+    while let Some(buffer) = repl.read() {
+        if let Some(command) = ReplCommand::parse(&buffer) {
+            if let ReplCommand::Quit = command {
+                break;
+            }
+            repl.execute(&mut lox, &command, opt.ast_format, opt.tokens_format);
+        } else if opt.tokens {
+            let tokens = lox.tokens(&buffer, opt.tokens_format);
+            logger.borrow_mut().println(tokens);
+        } else if opt.print_ast() {
+            if let Ok(ast) = lox.ast(&buffer, opt.ast_format) {
+                match ast_out.as_mut() {
+                    Some(file) => writeln!(file, "{ast}")?,
+                    None => logger.borrow_mut().println(ast),
+                }
+            }
+        } else {
+            lox.run(&buffer).ok();
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,122 @@
+use crate::lox::LoxValue;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+};
+
+// The value of `{"a": 1, "b": 2}`, the entries are kept in the order they were added.
+//
+// Keys are compared exactly, unlike `==` which compares numbers approximately:
+// an approximate equality isn't transitive so it can't be hashed. `m[0.1 + 0.2]`
+// is not `m[0.3]` even if `0.1 + 0.2 == 0.3` is true. Only the values that can't
+// change are keys, lists and maps are not.
+
+/// A value that can be a key, see `Key::new`
+#[derive(Debug, Clone)]
+pub struct Key(LoxValue);
+
+impl Key {
+    pub fn new(value: LoxValue) -> Result<Self, String> {
+        match value {
+            LoxValue::Number(value) if value.is_nan() => {
+                Err(String::from("NaN can't be a map key"))
+            }
+            // -0 and 0 are the same key
+            LoxValue::Number(value) if value.to_bits() == (-0.0_f64).to_bits() => {
+                Ok(Key(LoxValue::Number(0.0)))
+            }
+            LoxValue::Nil | LoxValue::Boolean(_) | LoxValue::Number(_) | LoxValue::String(_) => {
+                Ok(Key(value))
+            }
+            _ => Err(String::from(
+                "Map keys must be nil, booleans, numbers or strings",
+            )),
+        }
+    }
+
+    pub fn value(&self) -> &LoxValue {
+        &self.0
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (LoxValue::Nil, LoxValue::Nil) => true,
+            (LoxValue::Boolean(a), LoxValue::Boolean(b)) => a == b,
+            (LoxValue::Number(a), LoxValue::Number(b)) => a.to_bits() == b.to_bits(),
+            (LoxValue::String(a), LoxValue::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Key {}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            LoxValue::String(value) => write!(f, "\"{value}\""),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            LoxValue::Boolean(value) => value.hash(state),
+            LoxValue::Number(value) => value.to_bits().hash(state),
+            LoxValue::String(value) => value.hash(state),
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Key, LoxValue)>,
+    /// The index of the entry of each key
+    indices: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: &Key) -> Option<&LoxValue> {
+        self.indices.get(key).map(|index| &self.entries[*index].1)
+    }
+
+    /// A key that is already in the map keeps its place
+    pub fn insert(&mut self, key: Key, value: LoxValue) {
+        if let Some(index) = self.indices.get(&key) {
+            self.entries[*index].1 = value;
+        } else {
+            self.indices.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<LoxValue> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for (key, _) in &self.entries[index..] {
+            if let Some(index) = self.indices.get_mut(key) {
+                *index -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&LoxValue, &LoxValue)> {
+        self.entries.iter().map(|(key, value)| (key.value(), value))
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{
+    interpreter::{item_index, Interpreter},
+    lox::LoxValue,
+    map::Key,
+};
+use float_cmp::{ApproxEq, F64Margin};
+use std::{cell::RefCell, cmp::Ordering};
+
+// The methods of the builtin types, called like `(0..10).contains(5)`.
+//
+// `value.name` is a `Function::Method` holding `value`, calling it runs `call`.
+//
+// Lox has no function declarations yet, so the functions given to `map`, `filter` and
+// `reduce` are natives or methods of other values, like `names.filter(allowed.has)`
+// or `numbers.reduce(max, 0)`.
+
+/// The number of arguments of the method, `None` if the value doesn't have it
+pub fn arity(receiver: &LoxValue, name: &str) -> Option<usize> {
+    match (receiver, name) {
+        (
+            LoxValue::Range(_) | LoxValue::String(_) | LoxValue::List(_) | LoxValue::Map(_),
+            "len",
+        )
+        | (LoxValue::List(_), "pop" | "sort")
+        | (LoxValue::Map(_), "keys" | "values") => Some(0),
+        (LoxValue::Range(_), "contains" | "step")
+        | (LoxValue::List(_), "push" | "remove" | "map" | "filter" | "join")
+        | (LoxValue::Map(_), "has" | "remove") => Some(1),
+        (LoxValue::List(_), "insert" | "reduce") => Some(2),
+        _ => None,
+    }
+}
+
+/// Calls a function given to a method, like the function of `list.map(f)`
+fn call_function(
+    interpreter: &mut Interpreter,
+    function: &LoxValue,
+    args: &[LoxValue],
+) -> Result<LoxValue, String> {
+    match function {
+        LoxValue::Function(function) if function.arity() == args.len() => {
+            function.call(interpreter, args)
+        }
+        LoxValue::Function(function) => Err(format!(
+            "Expected a function of {} arguments but got one of {}",
+            args.len(),
+            function.arity()
+        )),
+        _ => Err(String::from("Expected a function")),
+    }
+}
+
+/// The items are copied, the function can change the list while it goes through it
+fn list_items(list: &RefCell<Vec<LoxValue>>) -> Vec<LoxValue> {
+    list.borrow().clone()
+}
+
+fn compare_items(a: &LoxValue, b: &LoxValue) -> Option<Ordering> {
+    match (a, b) {
+        (LoxValue::Number(a), LoxValue::Number(b)) => a.partial_cmp(b),
+        (LoxValue::String(a), LoxValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Strings are quoted, `"1"` doesn't look like `1` in a message
+fn quoted(value: &LoxValue) -> String {
+    match value {
+        LoxValue::String(value) => format!("\"{value}\""),
+        _ => value.to_string(),
+    }
+}
+
+/// Every item can be compared to the others when each one can be compared to the next,
+/// the items are all numbers other than NaN or all strings
+fn sort(list: &RefCell<Vec<LoxValue>>) -> Result<(), String> {
+    let mut items = list.borrow_mut();
+    let incomparable = items
+        .windows(2)
+        .find(|pair| matches!(pair, [a, b] if compare_items(a, b).is_none()));
+    if let Some([a, b]) = incomparable {
+        return Err(format!(
+            "Can't compare {} and {}, only lists of numbers or lists of strings can be sorted",
+            quoted(a),
+            quoted(b)
+        ));
+    }
+    items.sort_by(|a, b| compare_items(a, b).expect("the items should be comparable"));
+    Ok(())
+}
+
+fn number_arg(value: &LoxValue) -> Result<f64, String> {
+    match value {
+        LoxValue::Number(value) => Ok(*value),
+        _ => Err(String::from("Indices must be numbers")),
+    }
+}
+
+/// The errors are reported at the call by the interpreter
+pub fn call(
+    interpreter: &mut Interpreter,
+    receiver: &LoxValue,
+    name: &str,
+    args: &[LoxValue],
+) -> Result<LoxValue, String> {
+    match (receiver, name) {
+        (LoxValue::Range(range), "len") => Ok(range.len().into()),
+        (LoxValue::Range(range), "contains") => match args[0] {
+            LoxValue::Number(value) => Ok(range.contains(value).into()),
+            _ => Ok(false.into()),
+        },
+        (LoxValue::Range(range), "step") => match args[0] {
+            LoxValue::Number(step) => Ok(LoxValue::Range(range.with_step(step)?)),
+            _ => Err(String::from("The step of a range must be a number")),
+        },
+        #[allow(clippy::cast_precision_loss)]
+        (LoxValue::String(value), "len") => Ok((value.chars().count() as f64).into()),
+        #[allow(clippy::cast_precision_loss)]
+        (LoxValue::List(list), "len") => Ok((list.borrow().len() as f64).into()),
+        (LoxValue::List(list), "push") => {
+            list.borrow_mut().push(args[0].clone());
+            Ok(LoxValue::Nil)
+        }
+        (LoxValue::List(list), "pop") => Ok(list.borrow_mut().pop().unwrap_or(LoxValue::Nil)),
+        (LoxValue::List(list), "insert") => {
+            let mut items = list.borrow_mut();
+            // Inserting at the length adds to the end
+            let index = number_arg(&args[0])?;
+            #[allow(clippy::cast_precision_loss)]
+            let index = if index.approx_eq(items.len() as f64, F64Margin::default()) {
+                items.len()
+            } else {
+                item_index(index, items.len())?
+            };
+            items.insert(index, args[1].clone());
+            Ok(LoxValue::Nil)
+        }
+        (LoxValue::List(list), "remove") => {
+            let mut items = list.borrow_mut();
+            let index = item_index(number_arg(&args[0])?, items.len())?;
+            Ok(items.remove(index))
+        }
+        (LoxValue::List(list), "map") => {
+            let items: Result<Vec<LoxValue>, String> = list_items(list)
+                .into_iter()
+                .map(|item| call_function(interpreter, &args[0], &[item]))
+                .collect();
+            Ok(items?.into())
+        }
+        (LoxValue::List(list), "filter") => {
+            let mut items = Vec::new();
+            for item in list_items(list) {
+                if call_function(interpreter, &args[0], std::slice::from_ref(&item))?.is_truthy() {
+                    items.push(item);
+                }
+            }
+            Ok(items.into())
+        }
+        (LoxValue::List(list), "reduce") => list_items(list)
+            .into_iter()
+            .try_fold(args[1].clone(), |accumulator, item| {
+                call_function(interpreter, &args[0], &[accumulator, item])
+            }),
+        (LoxValue::List(list), "sort") => {
+            sort(list)?;
+            Ok(LoxValue::Nil)
+        }
+        (LoxValue::List(list), "join") => match &args[0] {
+            LoxValue::String(separator) => Ok(list
+                .borrow()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(separator)
+                .into()),
+            _ => Err(String::from("The separator must be a string")),
+        },
+        #[allow(clippy::cast_precision_loss)]
+        (LoxValue::Map(map), "len") => Ok((map.borrow().len() as f64).into()),
+        (LoxValue::Map(map), "keys") => {
+            let keys: Vec<LoxValue> = map.borrow().entries().map(|(key, _)| key.clone()).collect();
+            Ok(keys.into())
+        }
+        (LoxValue::Map(map), "values") => {
+            let values: Vec<LoxValue> = map
+                .borrow()
+                .entries()
+                .map(|(_, value)| value.clone())
+                .collect();
+            Ok(values.into())
+        }
+        // A value that can't be a key is not in the map
+        (LoxValue::Map(map), "has") => Ok(Key::new(args[0].clone())
+            .is_ok_and(|key| map.borrow().contains(&key))
+            .into()),
+        (LoxValue::Map(map), "remove") => Ok(Key::new(args[0].clone())
+            .ok()
+            .and_then(|key| map.borrow_mut().remove(&key))
+            .unwrap_or(LoxValue::Nil)),
+        _ => unreachable!("methods should only be called when they have an arity"),
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     ast::{Expr, Stmt},
     logger::{Logger, LoggerImpl},
-    lox::{ErrorData, LoxError, LoxResult},
+    lox::{LoxError, LoxResult},
     token::{Literal, Token, TokenType},
 };
 use std::{cell::RefCell, rc::Rc};
@@ -10,6 +10,23 @@ pub struct Parser<'a> {
     tokens: Vec<Token>,
     current: usize,
     logger: &'a Rc<RefCell<LoggerImpl<'a>>>,
+    /// Allows the last expression of the input to omit its `;`,
+    /// the last expression of a block never needs one
+    pub is_repl: bool,
+    /// Parses `if` and `while` like jlox, with parentheses and any statement as body
+    pub is_compat: bool,
+    /// An error that was reported without stopping the parser
+    error: Option<LoxError>,
+    context: Context,
+}
+
+/// What the parser knows about the code around the statement it parses
+#[derive(Default)]
+struct Context {
+    /// The loops being parsed, the innermost one last, with their label
+    loops: Vec<Option<String>>,
+    /// The statement is part of an expression, the value of its blocks is used
+    is_expression: bool,
 }
 
 macro_rules! match_tokens {
@@ -23,8 +40,31 @@ macro_rules! match_tokens {
     };
 }
 
+/// Whether the tokens after a `{` are the entries of a map instead of the statements
+/// of a block, a map is empty or has a `:` before the end of the first statement
+///
+/// The `:` after a label is part of a statement.
+pub fn is_map_start(token_types: impl IntoIterator<Item = TokenType>) -> bool {
+    let mut depth = 0_usize;
+    let mut previous = TokenType::LEFT_BRACE;
+    for token_type in token_types {
+        match token_type {
+            TokenType::RIGHT_BRACE if depth == 0 => return previous == TokenType::LEFT_BRACE,
+            TokenType::LEFT_PAREN | TokenType::LEFT_BRACE | TokenType::LEFT_BRACKET => depth += 1,
+            TokenType::RIGHT_PAREN | TokenType::RIGHT_BRACE | TokenType::RIGHT_BRACKET => {
+                depth = depth.saturating_sub(1);
+            }
+            TokenType::COLON if depth == 0 => return previous != TokenType::LABEL,
+            TokenType::SEMICOLON | TokenType::EOF if depth == 0 => return false,
+            _ => (),
+        }
+        previous = token_type;
+    }
+    false
+}
+
 // TODO
-// * add a context struct with a loop flag
+// * reject `return` outside of a function once functions are parsed, with a flag in `Context`
 // * the context could also have information about undefined or immutable variables
 
 impl<'a> Parser<'a> {
@@ -33,17 +73,49 @@ impl<'a> Parser<'a> {
             tokens,
             current: 0,
             logger,
+            is_repl: false,
+            is_compat: false,
+            error: None,
+            context: Context::default(),
         }
     }
 
     /// program -> declaration* EOF ;
     pub fn parse(&mut self) -> LoxResult<Vec<Stmt>> {
+        let statements: LoxResult<Vec<Stmt>> = self.declarations().into_iter().collect();
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => statements,
+        }
+    }
+
+    /// Parses like `parse` but keeps the statements without errors, for the language server
+    ///
+    /// The errors are only reported to the logger.
+    pub fn parse_lenient(&mut self) -> Vec<Stmt> {
+        self.declarations()
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect()
+    }
+
+    fn declarations(&mut self) -> Vec<LoxResult<Stmt>> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
             let stmt = self.declaration();
-            statements.push(stmt)
+            statements.push(stmt);
+        }
+        statements
+    }
+
+    /// Parses a single expression up to the end of the tokens
+    pub fn parse_expression(&mut self) -> LoxResult<Expr> {
+        let expr = self.expression()?;
+        if self.is_at_end() {
+            Ok(expr)
+        } else {
+            Err(self.error("Expected end of expression"))
         }
-        statements.into_iter().collect()
     }
 
     /// declaration -> `let_decl`
@@ -85,38 +157,127 @@ impl<'a> Parser<'a> {
     ///            | `if_stmt`
     ///            | `print_stmt`
     ///            | `while_stmt`
-    ///            | break
-    ///            | continue
+    ///            | `labeled_stmt`
+    ///            | "break" LABEL? expression? ";"
+    ///            | "continue" LABEL? ";"
     ///            | block ;
     fn statement(&mut self) -> LoxResult<Stmt> {
         if match_tokens!(self, TokenType::FOR) {
-            self.for_statement()
+            self.for_statement(None)
         } else if match_tokens!(self, TokenType::IF) {
             self.if_statement()
         } else if match_tokens!(self, TokenType::PRINT) {
             self.print_statement()
         } else if match_tokens!(self, TokenType::WHILE) {
-            self.while_statement()
+            self.while_statement(None)
         } else if match_tokens!(self, TokenType::LOOP) {
-            self.loop_statement()
+            self.loop_statement(None)
+        } else if match_tokens!(self, TokenType::LABEL) {
+            self.labeled_statement()
         } else if match_tokens!(self, TokenType::LEFT_BRACE) {
             Ok(Stmt::Block(self.block()?))
         } else if match_tokens!(self, TokenType::BREAK) {
+            let keyword = self.previous().clone();
+            self.check_inside_loop(&keyword);
+            let label = self.target_label();
+            let value = match self.check() {
+                Some(TokenType::SEMICOLON) => None,
+                _ => Some(self.expression()?),
+            };
             self.consume(TokenType::SEMICOLON, "Expected ';' after break")?;
-            Ok(Stmt::Break(self.previous().clone()))
+            Ok(Stmt::Break(keyword, label, value))
         } else if match_tokens!(self, TokenType::CONTINUE) {
+            let keyword = self.previous().clone();
+            self.check_inside_loop(&keyword);
+            let label = self.target_label();
             self.consume(TokenType::SEMICOLON, "Expected ';' after continue")?;
-            Ok(Stmt::Continue(self.previous().clone()))
+            Ok(Stmt::Continue(keyword, label))
         } else {
             self.expression_statement()
         }
     }
 
+    /// `labeled_stmt` -> LABEL ":" ( `for_stmt` | `while_stmt` | `loop_stmt` ) ;
+    fn labeled_statement(&mut self) -> LoxResult<Stmt> {
+        let label = self.previous().clone();
+        self.consume(TokenType::COLON, "Expected ':' after label")?;
+        if match_tokens!(self, TokenType::FOR) {
+            self.for_statement(Some(label))
+        } else if match_tokens!(self, TokenType::WHILE) {
+            self.while_statement(Some(label))
+        } else if match_tokens!(self, TokenType::LOOP) {
+            self.loop_statement(Some(label))
+        } else {
+            Err(self.error("Expected a loop after a label"))
+        }
+    }
+
+    /// Parses the body of a loop, the `break` and `continue` inside it can target the loop
+    fn loop_body(
+        &mut self,
+        label: Option<&Token>,
+        body: fn(&mut Self) -> LoxResult<Stmt>,
+    ) -> LoxResult<Stmt> {
+        self.context
+            .loops
+            .push(label.map(|label| label.lexeme.clone()));
+        // The value of a loop comes from its `break`, not from its body
+        let is_expression = std::mem::replace(&mut self.context.is_expression, false);
+        let body = body(self);
+        self.context.is_expression = is_expression;
+        self.context.loops.pop();
+        body
+    }
+
+    /// Reports a `break` or a `continue` outside of a loop
+    fn check_inside_loop(&mut self, keyword: &Token) {
+        if self.context.loops.is_empty() {
+            // The parser doesn't need to synchronise, the rest of the statement is fine
+            let message = format!("'{}' must be inside a loop", keyword.lexeme);
+            let error = self.error_token(keyword, &message);
+            self.error = Some(error);
+        }
+    }
+
+    /// The label after `break` or `continue`, it has to be the label of an enclosing loop
+    fn target_label(&mut self) -> Option<Token> {
+        if !match_tokens!(self, TokenType::LABEL) {
+            return None;
+        }
+        let label = self.previous().clone();
+        let is_known = self
+            .context
+            .loops
+            .iter()
+            .any(|loop_label| loop_label.as_ref() == Some(&label.lexeme));
+        if !is_known {
+            let message = format!("Unknown label {}", label.lexeme);
+            let error = self.error_token(&label, &message);
+            self.error = Some(error);
+        }
+        Some(label)
+    }
+
     /// `for_stmt` -> "for" "(" ( `let_decl` | `expr_stmt` | ";" )
     ///                       expression? ";"
-    ///                       expression? ")" statement ;
-    /// TODO "for" IDENTIFIER "in" IDENTIFIER block ;
-    fn for_statement(&mut self) -> LoxResult<Stmt> {
+    ///                       expression? ")" statement
+    ///             | "for" IDENTIFIER "in" expression `block_statement` ;
+    /// compat: only the first form
+    fn for_statement(&mut self, label: Option<Token>) -> LoxResult<Stmt> {
+        let keyword = self.previous().clone();
+        if !self.is_compat && match_tokens!(self, TokenType::IDENTIFIER) {
+            let variable = self.previous().clone();
+            self.consume(TokenType::IN, "Expected 'in' after the loop variable")?;
+            let iterable = self.expression()?;
+            let body = self.loop_body(label.as_ref(), Self::block_statement)?;
+            return Ok(Stmt::ForIn(
+                keyword,
+                label,
+                variable,
+                iterable,
+                Box::new(body),
+            ));
+        }
         self.consume(TokenType::LEFT_PAREN, "Expected '(' after 'for'")?;
         let initializer = if match_tokens!(self, TokenType::SEMICOLON) {
             None
@@ -138,16 +299,16 @@ impl<'a> Parser<'a> {
         };
         self.consume(TokenType::RIGHT_PAREN, "Expected ')' after for clauses")?;
 
-        let mut body = self.statement()?;
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
-        }
+        let body = self.loop_body(label.as_ref(), Self::statement)?;
         if condition.is_none() {
-            condition = Some(Expr::Literal(Literal::TRUE))
+            condition = Some(Expr::Literal(Literal::TRUE));
         }
-        body = Stmt::While(
+        let mut body = Stmt::While(
+            keyword,
+            label,
             condition.expect("condition should be Some() at this point"),
             Box::new(body),
+            increment,
         );
         if let Some(initializer) = initializer {
             body = Stmt::Block(vec![initializer, body]);
@@ -155,25 +316,42 @@ impl<'a> Parser<'a> {
         Ok(body)
     }
 
-    /// "loop" `block_statement` ;
-    fn loop_statement(&mut self) -> LoxResult<Stmt> {
-        let body = self.block_statement()?;
-        Ok(Stmt::While(Expr::Literal(Literal::TRUE), Box::new(body)))
-    }
-
-    /// "while" expression `block_statement` ;
-    fn while_statement(&mut self) -> LoxResult<Stmt> {
+    /// `loop_stmt` -> "loop" `block_statement` ;
+    fn loop_statement(&mut self, label: Option<Token>) -> LoxResult<Stmt> {
+        let keyword = self.previous().clone();
+        let body = self.loop_body(label.as_ref(), Self::block_statement)?;
+        Ok(Stmt::While(
+            keyword,
+            label,
+            Expr::Literal(Literal::TRUE),
+            Box::new(body),
+            None,
+        ))
+    }
+
+    /// `while_stmt` -> "while" expression `block_statement` ;
+    /// compat: "while" "(" expression ")" statement ;
+    fn while_statement(&mut self, label: Option<Token>) -> LoxResult<Stmt> {
+        let keyword = self.previous().clone();
+        if self.is_compat {
+            self.consume(TokenType::LEFT_PAREN, "Expected '(' after 'while'")?;
+            let condition = self.expression()?;
+            self.consume(TokenType::RIGHT_PAREN, "Expected ')' after condition")?;
+            let body = self.loop_body(label.as_ref(), Self::statement)?;
+            return Ok(Stmt::While(keyword, label, condition, Box::new(body), None));
+        }
         let condition = self.expression()?;
-        let body = self.block_statement()?;
-        Ok(Stmt::While(condition, Box::new(body)))
+        let body = self.loop_body(label.as_ref(), Self::block_statement)?;
+        Ok(Stmt::While(keyword, label, condition, Box::new(body), None))
     }
 
     /// `print_stmt` -> "print" expression ";" ;
     /// TODO remove this when we have a standard library
     fn print_statement(&mut self) -> LoxResult<Stmt> {
-        let value = self.expression();
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
         self.consume(TokenType::SEMICOLON, "Expect ';' after value")?;
-        value.and_then(|value| Ok(Stmt::Print(value)))
+        Ok(Stmt::Print(keyword, value))
     }
 
     /// `block_statement` -> "{" block ;
@@ -193,24 +371,45 @@ impl<'a> Parser<'a> {
     }
 
     /// `if_stmt` -> "if" expression block ( "else" block )? ;
+    /// compat: "if" "(" expression ")" statement ( "else" statement )? ;
     fn if_statement(&mut self) -> LoxResult<Stmt> {
-        let condition = self.expression()?;
-        let then_branch = self.block_statement()?;
+        let keyword = self.previous().clone();
+        let (condition, then_branch) = if self.is_compat {
+            self.consume(TokenType::LEFT_PAREN, "Expected '(' after 'if'")?;
+            let condition = self.expression()?;
+            self.consume(TokenType::RIGHT_PAREN, "Expected ')' after if condition")?;
+            (condition, self.statement()?)
+        } else {
+            (self.expression()?, self.block_statement()?)
+        };
         let else_branch = if match_tokens!(self, TokenType::ELSE) {
             Some(Box::new(self.statement()?))
         } else {
             None
         };
 
-        Ok(Stmt::If(condition, Box::new(then_branch), else_branch))
+        Ok(Stmt::If(
+            keyword,
+            condition,
+            Box::new(then_branch),
+            else_branch,
+        ))
     }
 
     /// `expr_stmt`  -> expression ";"
+    ///
+    /// The ";" is optional at the end of a block, where the expression is the value
+    /// of the block, and in the repl at the end of the input
     fn expression_statement(&mut self) -> LoxResult<Stmt> {
-        // TODO support expression with no ;
-        let expr = self.expression();
-        self.consume(TokenType::SEMICOLON, "Expect ';' after expression")?;
-        expr.and_then(|expr| Ok(Stmt::Expression(expr)))
+        let expr = self.expression()?;
+        // The last expression of a block doesn't need a `;` when the value of the block is used
+        let is_last_of_block = (self.is_repl || self.context.is_expression)
+            && self.check_token(TokenType::RIGHT_BRACE);
+        let is_last_of_input = self.is_repl && self.is_at_end();
+        if !(is_last_of_block || is_last_of_input) {
+            self.consume(TokenType::SEMICOLON, "Expect ';' after expression")?;
+        }
+        Ok(Stmt::Expression(expr))
     }
 
     /// expression -> assignment ;
@@ -218,23 +417,38 @@ impl<'a> Parser<'a> {
         self.assignment()
     }
 
-    /// assignment -> IDENTIFIER "=" assignment
-    ///             | `logic_or` ;
+    /// assignment -> ( IDENTIFIER | `function_call` "[" expression "]" ) "=" assignment
+    ///             | range ;
+    #[allow(clippy::doc_link_with_quotes)]
     fn assignment(&mut self) -> LoxResult<Expr> {
-        let expr = self.logic_or();
+        let expr = self.range()?;
 
         if match_tokens!(self, TokenType::EQUAL) {
             let equals = self.previous().clone();
+            let value = self.assignment()?;
             match expr {
-                Ok(Expr::Variable(token)) => {
-                    let value = self.assignment()?;
-                    Ok(Expr::Assign(token, Box::new(value)))
+                Expr::Variable(token) => return Ok(Expr::Assign(token, Box::new(value))),
+                Expr::Index(object, bracket, index) => {
+                    return Ok(Expr::SetIndex(object, bracket, index, Box::new(value)))
                 }
-                _ => Err(self.error_token(&equals, "Invalid assignment target")),
+                _ => (),
             }
-        } else {
-            expr
+            // The parser doesn't need to synchronise, the rest of the statement is fine
+            let error = self.error_token(&equals, "Invalid assignment target");
+            self.error = Some(error);
+        }
+        Ok(expr)
+    }
+
+    /// range -> `logic_or` ( ( ".." | "..=" ) `logic_or` )? ;
+    fn range(&mut self) -> LoxResult<Expr> {
+        let expr = self.logic_or()?;
+        if match_tokens!(self, TokenType::DOT_DOT, TokenType::DOT_DOT_EQUAL) {
+            let operator = self.previous().clone();
+            let end = self.logic_or()?;
+            return Ok(Expr::Range(Box::new(expr), operator, Box::new(end)));
         }
+        Ok(expr)
     }
 
     /// `logic_or` -> `logic_and` ( "or" `logic_and` )* ;
@@ -321,13 +535,24 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// `function_call` -> primary ( "(" arguments? ")" )* ;
+    /// `function_call` -> primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
     /// arguments -> expression ( "," expression )*;
+    #[allow(clippy::doc_link_with_quotes)]
     fn function_call(&mut self) -> LoxResult<Expr> {
         let mut expr = self.primary()?;
         loop {
             if match_tokens!(self, TokenType::LEFT_PAREN) {
                 expr = self.finish_function_call(expr)?;
+            } else if match_tokens!(self, TokenType::DOT) {
+                let name = self
+                    .consume(TokenType::IDENTIFIER, "Expected property name after '.'")?
+                    .clone();
+                expr = Expr::Get(Box::new(expr), name);
+            } else if match_tokens!(self, TokenType::LEFT_BRACKET) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume(TokenType::RIGHT_BRACKET, "Expected ']' after index")?;
+                expr = Expr::Index(Box::new(expr), bracket, Box::new(index));
             } else {
                 break;
             }
@@ -355,7 +580,16 @@ impl<'a> Parser<'a> {
     /// primary -> "true" | "false" | "nil"
     ///          | NUMBER | STRING
     ///          | "(" expression ")"
-    ///          | IDENTIFIER ;
+    ///          | IDENTIFIER
+    ///          | "[" ( expression ( "," expression )* ","? )? "]"
+    ///          | "{" ( entry ( "," entry )* ","? )? "}"
+    ///          | `if_stmt` | `loop_stmt` | `labeled_stmt` | `block_statement` ;
+    /// entry -> expression ":" expression ;
+    ///
+    /// A `{` starts a map when `is_map_start`, `{}` is an empty map.
+    ///
+    /// compat: `if`, `loop` and blocks are only statements, there are no maps
+    #[allow(clippy::doc_link_with_quotes)]
     fn primary(&mut self) -> LoxResult<Expr> {
         if match_tokens!(
             self,
@@ -375,11 +609,64 @@ impl<'a> Parser<'a> {
             let expr = self.expression()?;
             self.consume(TokenType::RIGHT_PAREN, "Expected ')' after expression")?;
             Ok(Expr::Grouping(Box::new(expr)))
+        } else if match_tokens!(self, TokenType::LEFT_BRACKET) {
+            let bracket = self.previous().clone();
+            let mut items = Vec::new();
+            while !self.check_token(TokenType::RIGHT_BRACKET) {
+                items.push(self.expression()?);
+                if !match_tokens!(self, TokenType::COMMA) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RIGHT_BRACKET, "Expected ']' after list items")?;
+            Ok(Expr::List(bracket, items))
+        } else if !self.is_compat
+            && self.check_token(TokenType::LEFT_BRACE)
+            && is_map_start(
+                self.tokens[self.current + 1..]
+                    .iter()
+                    .map(|token| token.token_type),
+            )
+        {
+            self.map()
+        } else if !self.is_compat
+            && self.check().is_some_and(|token_type| {
+                matches!(
+                    token_type,
+                    TokenType::IF | TokenType::LOOP | TokenType::LABEL | TokenType::LEFT_BRACE
+                )
+            })
+        {
+            self.statement_expression()
         } else {
             Err(self.error("Expected expression"))
         }
     }
 
+    fn map(&mut self) -> LoxResult<Expr> {
+        let brace = self.advance().clone();
+        let mut entries = Vec::new();
+        while !self.check_token(TokenType::RIGHT_BRACE) {
+            let key = self.expression()?;
+            self.consume(TokenType::COLON, "Expected ':' after map key")?;
+            entries.push((key, self.expression()?));
+            if !match_tokens!(self, TokenType::COMMA) {
+                break;
+            }
+        }
+        self.consume(TokenType::RIGHT_BRACE, "Expected '}' after map entries")?;
+        Ok(Expr::Map(brace, entries))
+    }
+
+    /// A statement used as an expression, `break` and `continue` inside it can target
+    /// the loops around the expression
+    fn statement_expression(&mut self) -> LoxResult<Expr> {
+        let is_expression = std::mem::replace(&mut self.context.is_expression, true);
+        let stmt = self.statement();
+        self.context.is_expression = is_expression;
+        Ok(Expr::Statement(Box::new(stmt?)))
+    }
+
     fn consume(&mut self, token_type: TokenType, error_msg: &str) -> LoxResult<&Token> {
         if let Some(check_token) = self.check() {
             if check_token == token_type {
@@ -452,8 +739,7 @@ impl<'a> Parser<'a> {
     }
 
     fn error_token(&mut self, token: &Token, message: &str) -> LoxError {
-        let message = String::from(message);
-        self.logger.borrow_mut().error(token, message.clone());
-        LoxError::Parser(ErrorData::new(token.clone(), message))
+        self.logger.borrow_mut().error(token, String::from(message));
+        LoxError::Parser
     }
 }
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter, Write},
+    time::{Duration, Instant},
+};
+
+// Times the statements and the function calls of a program.
+//
+// The statements running are kept in a stack, the self time of a statement is its time
+// without the time of the statements and calls it runs, like the body of a `while`.
+// The stacks are also recorded in the folded format of flamegraph tools,
+// one line per stack with its frames separated by `;` and its self time.
+
+/// The bottom of every stack, the statements at the top level of the program
+const ROOT: &str = "<script>";
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timing {
+    pub hits: usize,
+    pub total: Duration,
+    pub self_time: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, total: Duration, self_time: Duration) {
+        self.hits += 1;
+        self.total += total;
+        self.self_time += self_time;
+    }
+}
+
+enum Frame {
+    Line(usize),
+    Function(String),
+}
+
+struct Running {
+    frame: Frame,
+    start: Instant,
+    /// The time spent in the frames above this one
+    children: Duration,
+}
+
+pub struct Profiler {
+    /// The name of the file in the folded stacks
+    file: String,
+    source: Vec<String>,
+    pub lines: HashMap<usize, Timing>,
+    pub functions: HashMap<String, Timing>,
+    /// The self time of each stack, in the folded format
+    folded: HashMap<String, Duration>,
+    stack: Vec<Running>,
+}
+
+impl Profiler {
+    pub fn new(file: &str, source: &str) -> Self {
+        Profiler {
+            file: String::from(file),
+            source: source.lines().map(String::from).collect(),
+            lines: HashMap::new(),
+            functions: HashMap::new(),
+            folded: HashMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn enter_line(&mut self, line: usize) {
+        self.enter(Frame::Line(line));
+    }
+
+    pub fn enter_function(&mut self, name: &str) {
+        self.enter(Frame::Function(String::from(name)));
+    }
+
+    fn enter(&mut self, frame: Frame) {
+        self.stack.push(Running {
+            frame,
+            start: Instant::now(),
+            children: Duration::default(),
+        });
+    }
+
+    /// Stops the timer of the last frame entered
+    pub fn exit(&mut self) {
+        let path = self.path();
+        let Some(running) = self.stack.pop() else {
+            return;
+        };
+        let total = running.start.elapsed();
+        let self_time = total.saturating_sub(running.children);
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += total;
+        }
+        *self.folded.entry(path).or_default() += self_time;
+        match running.frame {
+            Frame::Line(line) => self.lines.entry(line).or_default(),
+            Frame::Function(name) => self.functions.entry(name).or_default(),
+        }
+        .record(total, self_time);
+    }
+
+    fn label(&self, frame: &Frame) -> String {
+        match frame {
+            Frame::Line(line) => format!("{}:{}", self.file, line),
+            Frame::Function(name) => format!("{name}()"),
+        }
+    }
+
+    /// The frames of the current stack in the folded format
+    fn path(&self) -> String {
+        let mut path = String::from(ROOT);
+        for running in &self.stack {
+            path.push(';');
+            path.push_str(&self.label(&running.frame));
+        }
+        path
+    }
+
+    /// One line per stack with its self time in microseconds, sorted by stack
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(&String, &Duration)> = self.folded.iter().collect();
+        stacks.sort();
+        let mut folded = String::new();
+        for (path, time) in stacks {
+            writeln!(folded, "{} {}", path, time.as_micros()).expect("Failed to write");
+        }
+        folded
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The report, lines and functions sorted by self time
+impl Display for Profiler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut lines: Vec<(&usize, &Timing)> = self.lines.iter().collect();
+        lines.sort_by(|(a_line, a), (b_line, b)| {
+            b.self_time.cmp(&a.self_time).then(a_line.cmp(b_line))
+        });
+        writeln!(
+            f,
+            "{:>6} {:>8} {:>12} {:>12}  source",
+            "line", "hits", "total ms", "self ms"
+        )?;
+        for (line, timing) in lines {
+            let source = self.source.get(line - 1).map_or("", |text| text.trim());
+            writeln!(
+                f,
+                "{:>6} {:>8} {:>12.3} {:>12.3}  {}",
+                line,
+                timing.hits,
+                millis(timing.total),
+                millis(timing.self_time),
+                source
+            )?;
+        }
+
+        let mut functions: Vec<(&String, &Timing)> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| {
+            b.self_time.cmp(&a.self_time).then(a_name.cmp(b_name))
+        });
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<12} {:>8} {:>12} {:>12}",
+            "function", "calls", "total ms", "self ms"
+        )?;
+        for (name, timing) in functions {
+            writeln!(
+                f,
+                "{:<12} {:>8} {:>12.3} {:>12.3}",
+                name,
+                timing.hits,
+                millis(timing.total),
+                millis(timing.self_time)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use float_cmp::{ApproxEq, F64Margin};
+use std::fmt::{self, Display, Formatter};
+
+// The value of `start..end` and `start..=end`, the numbers from `start` up to `end`.
+//
+// A step other than 1 comes from `range.step(n)`, a negative step counts down
+// like `(10..0).step(-1)`. The bounds and the step are integers, ranges also
+// slice strings and their items are indices.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    pub is_inclusive: bool,
+}
+
+fn is_integer(value: f64) -> bool {
+    value.is_finite() && value.fract().approx_eq(0.0, F64Margin::default())
+}
+
+impl Range {
+    pub fn new(start: f64, end: f64, is_inclusive: bool) -> Result<Self, String> {
+        if !is_integer(start) || !is_integer(end) {
+            return Err(String::from("Range bounds must be integers"));
+        }
+        Ok(Range {
+            start,
+            end,
+            step: 1.0,
+            is_inclusive,
+        })
+    }
+
+    pub fn with_step(self, step: f64) -> Result<Self, String> {
+        if !is_integer(step) || step.approx_eq(0.0, F64Margin::default()) {
+            return Err(String::from(
+                "The step of a range must be an integer other than 0",
+            ));
+        }
+        Ok(Range { step, ..self })
+    }
+
+    fn is_before_end(self, value: f64) -> bool {
+        match (self.step > 0.0, self.is_inclusive) {
+            (true, true) => value <= self.end,
+            (true, false) => value < self.end,
+            (false, true) => value >= self.end,
+            (false, false) => value > self.end,
+        }
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = f64> {
+        std::iter::successors(Some(self.start), move |value| Some(value + self.step))
+            .take_while(move |value| self.is_before_end(*value))
+    }
+
+    pub fn len(self) -> f64 {
+        let end = if self.is_inclusive {
+            self.end + self.step.signum()
+        } else {
+            self.end
+        };
+        ((end - self.start) / self.step).ceil().max(0.0)
+    }
+
+    /// Only the numbers the range goes through, `(0..4).step(2)` doesn't contain 1
+    pub fn contains(self, value: f64) -> bool {
+        let is_after_start = if self.step > 0.0 {
+            value >= self.start
+        } else {
+            value <= self.start
+        };
+        is_after_start
+            && self.is_before_end(value)
+            && ((value - self.start) % self.step).approx_eq(0.0, F64Margin::default())
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operator = if self.is_inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)?;
+        if self.step.approx_eq(1.0, F64Margin::default()) {
+            Ok(())
+        } else {
+            write!(f, " step {}", self.step)
+        }
+    }
+}
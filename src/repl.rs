@@ -0,0 +1,238 @@
+use crate::{
+    ast::AstFormat,
+    environment::Environment,
+    logger::Logger,
+    lox::Lox,
+    token::{TokenFormat, KEYWORDS},
+};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Editor, Helper,
+};
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc, time::Instant};
+
+const HISTORY_FILE: &str = ".lox_history";
+
+const HELP: &str = "\
+:help           Show this help
+:env            Print the variables of the session
+:ast <code>     Print the ast of the code
+:tokens <code>  Print the tokens of the code
+:load <file>    Run a file in the session
+:reset          Forget every variable of the session
+:time <code>    Run the code and print how long it took
+:quit           Exit the repl, same as exit";
+
+pub enum Command<'i> {
+    Help,
+    Env,
+    Ast(&'i str),
+    Tokens(&'i str),
+    Load(&'i str),
+    Reset,
+    Time(&'i str),
+    Quit,
+    Unknown(&'i str),
+}
+
+impl<'i> Command<'i> {
+    /// Parses a meta command, returns `None` when the input is lox code
+    pub fn parse(input: &'i str) -> Option<Self> {
+        let input = input.trim();
+        if input == "exit" {
+            return Some(Command::Quit);
+        }
+        let input = input.strip_prefix(':')?;
+        let (name, argument) = match input.find(char::is_whitespace) {
+            Some(i) => (&input[..i], input[i..].trim()),
+            None => (input, ""),
+        };
+        Some(match name {
+            "help" => Command::Help,
+            "env" => Command::Env,
+            "ast" => Command::Ast(argument),
+            "tokens" => Command::Tokens(argument),
+            "load" => Command::Load(argument),
+            "reset" => Command::Reset,
+            "time" => Command::Time(argument),
+            "quit" => Command::Quit,
+            _ => Command::Unknown(name),
+        })
+    }
+}
+
+pub struct Repl {
+    editor: Editor<LoxHelper, DefaultHistory>,
+    history: Option<PathBuf>,
+}
+
+impl Repl {
+    pub fn new(environment: Rc<RefCell<Environment>>) -> rustyline::Result<Self> {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(LoxHelper { environment }));
+        let history = dirs::home_dir().map(|home| home.join(HISTORY_FILE));
+        if let Some(ref history) = history {
+            // The history doesn't exist the first time the repl is used
+            editor.load_history(history).ok();
+        }
+        Ok(Repl { editor, history })
+    }
+
+    /// Reads a line, or multiple lines until braces and parentheses are balanced
+    ///
+    /// Returns `None` when the user wants to exit
+    pub fn read(&mut self) -> Option<String> {
+        loop {
+            match self.editor.readline("> ") {
+                Ok(input) => {
+                    if !input.trim().is_empty() {
+                        self.editor.add_history_entry(input.as_str()).ok();
+                        if let Some(ref history) = self.history {
+                            self.editor.save_history(history).ok();
+                        }
+                    }
+                    return Some(input);
+                }
+                Err(ReadlineError::Interrupted) => (), // ctrl-c only clears the input
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Runs every command except `Quit`, which is up to the caller
+    pub fn execute(
+        &mut self,
+        lox: &mut Lox,
+        command: &Command,
+        ast_format: AstFormat,
+        tokens_format: TokenFormat,
+    ) {
+        let output = match *command {
+            Command::Help => String::from(HELP),
+            Command::Env => lox.interpreter.environment.borrow().to_string(),
+            Command::Ast(code) => match lox.ast(code, ast_format) {
+                Ok(ast) => ast,
+                Err(_) => return,
+            },
+            Command::Tokens(code) => lox.tokens(code, tokens_format),
+            Command::Load(path) => match fs::read_to_string(path) {
+                Ok(source) => {
+                    // Files keep the strict grammar, even when loaded from the repl
+                    let is_repl = lox.is_repl;
+                    lox.is_repl = false;
+                    lox.run(&source).ok();
+                    lox.is_repl = is_repl;
+                    return;
+                }
+                Err(error) => format!("Could not read {path}: {error}"),
+            },
+            Command::Reset => {
+                lox.reset();
+                if let Some(helper) = self.editor.helper_mut() {
+                    helper.environment = lox.interpreter.environment.clone();
+                }
+                return;
+            }
+            Command::Time(code) => {
+                let start = Instant::now();
+                lox.run(code).ok();
+                format!("took {:?}", start.elapsed())
+            }
+            Command::Quit => return,
+            Command::Unknown(name) => format!("Unknown command :{name}, try :help"),
+        };
+        lox.logger.borrow_mut().println(output);
+    }
+}
+
+struct LoxHelper {
+    environment: Rc<RefCell<Environment>>,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let mut names: Vec<String> = KEYWORDS
+            .iter()
+            .map(|(keyword, _)| (*keyword).to_string())
+            .chain(self.environment.borrow().names())
+            .filter(|name| name.starts_with(word))
+            .collect();
+        names.sort();
+        names.dedup();
+        let candidates = names
+            .into_iter()
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Validator for LoxHelper {
+    fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+/// An input is incomplete while a brace, a parenthesis, a bracket, a string or a block comment
+/// is open
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0_i32;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            '"' if !chars.any(|c| c == '"') => return true,
+            '/' if chars.peek() == Some(&'/') => {
+                chars.find(|c| *c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                if !chars.any(|c| {
+                    let is_end = previous == '*' && c == '/';
+                    previous = c;
+                    is_end
+                }) {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+    depth > 0
+}
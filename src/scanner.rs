@@ -1,16 +1,22 @@
 use crate::{
+    cst::{SyntaxToken, Trivia},
     logger::{Logger, LoggerImpl},
-    token::{Literal, Position, Token, TokenType},
+    token::{keyword, Literal, Position, Token, TokenType},
 };
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, mem, rc::Rc};
 
 pub struct Scanner<'a> {
     source: String,
     tokens: Vec<Token>,
+    /// The trivia preceding each token, `leading_trivia[i]` goes before `tokens[i]`
+    leading_trivia: Vec<Vec<Trivia>>,
+    trivia: Vec<Trivia>,
     start: usize,
     current: usize,
     position: Position,
     logger: &'a Rc<RefCell<LoggerImpl<'a>>>,
+    /// Scans `in` as an identifier, jlox doesn't reserve it
+    pub is_compat: bool,
 }
 
 fn is_alphanumeric(c: char) -> bool {
@@ -22,29 +28,54 @@ impl<'a> Scanner<'a> {
         Scanner {
             source,
             tokens: Vec::new(),
+            leading_trivia: Vec::new(),
+            trivia: Vec::new(),
             start: 0,
             current: 0,
             position: Position { line: 1, column: 1 },
             logger,
+            is_compat: false,
         }
     }
 
     pub fn scan_tokens(&mut self) -> &[Token] {
         while !self.is_at_end() {
             self.start = self.current;
-            if let Some((token, literal)) = self.scan_token() {
-                self.add_token(token, literal);
+            match self.scan_token() {
+                Some((token, literal)) => self.add_token(token, literal),
+                None => self.add_trivia(),
             }
         }
         self.tokens.push(Token::new(
             TokenType::EOF,
-            String::from(""),
+            String::new(),
             None,
             self.position,
         ));
+        self.leading_trivia.push(mem::take(&mut self.trivia));
         &self.tokens
     }
 
+    /// Scans the tokens with the whitespace and comments attached to them as trivia
+    ///
+    /// A token owns the trivia following it on the same line, everything else
+    /// belongs to the next token. Concatenating the tokens gives back the source.
+    pub fn scan_lossless(&mut self) -> Vec<SyntaxToken> {
+        self.scan_tokens();
+        let mut syntax_tokens: Vec<SyntaxToken> = Vec::new();
+        for (token, mut leading) in self.tokens.iter().zip(self.leading_trivia.clone()) {
+            if let Some(previous) = syntax_tokens.last_mut() {
+                let end_of_line = leading
+                    .iter()
+                    .position(Trivia::is_newline)
+                    .unwrap_or(leading.len());
+                previous.trailing = leading.drain(..end_of_line).collect();
+            }
+            syntax_tokens.push(SyntaxToken::new(leading, token.clone(), Vec::new()));
+        }
+        syntax_tokens
+    }
+
     fn scan_token(&mut self) -> Option<(TokenType, Option<Literal>)> {
         let c = self.advance();
         match c {
@@ -52,11 +83,23 @@ impl<'a> Scanner<'a> {
             ')' => Some((TokenType::RIGHT_PAREN, None)),
             '{' => Some((TokenType::LEFT_BRACE, None)),
             '}' => Some((TokenType::RIGHT_BRACE, None)),
+            '[' => Some((TokenType::LEFT_BRACKET, None)),
+            ']' => Some((TokenType::RIGHT_BRACKET, None)),
             ',' => Some((TokenType::COMMA, None)),
-            '.' => Some((TokenType::DOT, None)),
+            '.' => Some((
+                if !self.advance_if_match('.') {
+                    TokenType::DOT
+                } else if self.advance_if_match('=') {
+                    TokenType::DOT_DOT_EQUAL
+                } else {
+                    TokenType::DOT_DOT
+                },
+                None,
+            )),
             '-' => Some((TokenType::MINUS, None)),
             '+' => Some((TokenType::PLUS, None)),
             ';' => Some((TokenType::SEMICOLON, None)),
+            ':' => Some((TokenType::COLON, None)),
             '*' => Some((TokenType::STAR, None)),
             '!' => Some((
                 if self.advance_if_match('=') {
@@ -91,12 +134,10 @@ impl<'a> Scanner<'a> {
                 None,
             )),
             '/' => self.comment_or_slash(),
-            ' ' | '\r' | '\t' => None, // ignore whitespace
-            '\n' => {
-                self.position.increment_line();
-                None
-            }
+            // ignore whitespace, advance already moved to the next line on '\n'
+            ' ' | '\r' | '\t' | '\n' => None,
             '"' => self.string(),
+            '\'' => self.label(),
             c if c.is_ascii_digit() => Some(self.number()),
             c if is_alphanumeric(c) => Some(self.identifier()),
             _ => {
@@ -104,7 +145,7 @@ impl<'a> Scanner<'a> {
                     self.position,
                     "Scanner",
                     "",
-                    format!("Unexpected character \"{}\"", c),
+                    format!("Unexpected character \"{c}\""),
                 );
                 None
             }
@@ -123,54 +164,46 @@ impl<'a> Scanner<'a> {
             literal,
             self.position,
         ));
+        self.leading_trivia.push(mem::take(&mut self.trivia));
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
+    fn add_trivia(&mut self) {
+        let text = &self.source[self.start..self.current];
+        match self.trivia.last_mut() {
+            Some(last) if last.can_merge(text) => last.text.push_str(text),
+            _ => self.trivia.push(Trivia::new(text)),
         }
-        self.source
-            .chars()
-            .nth(self.current)
-            .expect("current should exist")
+    }
+
+    // `current` is a byte index, the source is walked one char at a time
+    // so slicing it between `start` and `current` is always on a char boundary
+
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        self.source
-            .chars()
-            .nth(self.current + 1)
-            .expect("current + 1 should exist")
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
-        let c = self
-            .source
-            .chars()
-            .nth(self.current)
-            .expect("current should exist");
-        self.current += 1;
+        let c = self.peek();
+        assert!(!self.is_at_end(), "current should exist");
+        self.current += c.len_utf8();
         if c == '\n' {
-            self.position.increment_line()
+            self.position.increment_line();
         } else {
-            self.position.increment_column()
+            self.position.increment_column();
         }
         c
     }
 
     fn advance_if_match(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        if let Some(c) = self.source.chars().nth(self.current) {
-            if c == expected {
-                self.current += 1;
-                return true;
-            }
-        }
-        false
+        self.current += expected.len_utf8();
+        true
     }
 
     fn string(&mut self) -> Option<(TokenType, Option<Literal>)> {
@@ -235,6 +268,7 @@ impl<'a> Scanner<'a> {
                         "",
                         String::from("Unterminated block comment"),
                     );
+                    return None;
                 }
                 self.advance();
             }
@@ -243,32 +277,36 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// A `'` followed by an identifier, like the loop labels of rust
+    fn label(&mut self) -> Option<(TokenType, Option<Literal>)> {
+        let c = self.peek();
+        if !(c.is_alphabetic() || c == '_') {
+            self.logger.borrow_mut().report_error(
+                self.position,
+                "Scanner",
+                "",
+                String::from("Expected a label name after \"'\""),
+            );
+            return None;
+        }
+        while is_alphanumeric(self.peek()) {
+            self.advance();
+        }
+        Some((TokenType::LABEL, None))
+    }
+
     fn identifier(&mut self) -> (TokenType, Option<Literal>) {
         while is_alphanumeric(self.peek()) {
             self.advance();
         }
         let current_lexeme = &self.source[self.start..self.current];
-        match current_lexeme {
-            "and" => (TokenType::AND, None),
-            "class" => (TokenType::CLASS, None),
-            "else" => (TokenType::ELSE, None),
-            "false" => (TokenType::FALSE, Some(Literal::FALSE)),
-            "for" => (TokenType::FOR, None),
-            "fun" => (TokenType::FUN, None),
-            "if" => (TokenType::IF, None),
-            "nil" => (TokenType::NIL, Some(Literal::Nil)),
-            "or" => (TokenType::OR, None),
-            "print" => (TokenType::PRINT, None),
-            "return" => (TokenType::RETURN, None),
-            "super" => (TokenType::SUPER, None),
-            "this" => (TokenType::THIS, None),
-            "true" => (TokenType::TRUE, Some(Literal::TRUE)),
-            "let" | "var" => (TokenType::LET, None),
-            "while" => (TokenType::WHILE, None),
-            "loop" => (TokenType::LOOP, None),
-            "break" => (TokenType::BREAK, None),
-            "continue" => (TokenType::CONTINUE, None),
-            _ => (TokenType::IDENTIFIER, None),
+        match keyword(current_lexeme) {
+            Some(TokenType::FALSE) => (TokenType::FALSE, Some(Literal::FALSE)),
+            Some(TokenType::NIL) => (TokenType::NIL, Some(Literal::Nil)),
+            Some(TokenType::TRUE) => (TokenType::TRUE, Some(Literal::TRUE)),
+            Some(TokenType::IN) if self.is_compat => (TokenType::IDENTIFIER, None),
+            Some(token_type) => (token_type, None),
+            None => (TokenType::IDENTIFIER, None),
         }
     }
 }
@@ -1,46 +1,85 @@
 use crate::{
+    ast::{self, AstFormat},
+    conformance,
+    coverage::Coverage,
+    cst::{self, NodeKind, SyntaxElement},
+    dap::{self, Connection},
+    debugger::{Breakpoint, Debugger, Frontend},
+    golden::{self, Outcome},
+    highlight::{self, Highlight},
     logger::{LoggerImpl, TestLogger},
     lox::Lox,
+    lsp,
+    profiler::Profiler,
+    repl,
+    scanner::Scanner,
+    token::{TokenFormat, KEYWORDS},
+    tracer::Tracer,
+    transport::Transport,
+};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, Cursor, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
 };
-use std::{cell::RefCell, rc::Rc};
 
-fn lox_run(source: &str) -> Vec<u8> {
+/// Runs `f` with a logger writing to a buffer, returns the result of `f` and the output
+fn with_logger<T>(
+    is_repl: bool,
+    f: impl for<'a> FnOnce(&'a Rc<RefCell<LoggerImpl<'a>>>) -> T,
+) -> (T, String) {
     let mut output = Vec::new();
-    let logger = TestLogger::new(&mut output);
-    let logger = Rc::new(RefCell::new(LoggerImpl::from(logger)));
-    let mut lox = Lox::new(&logger, false, false);
-    let result = lox.run(source);
+    let result = {
+        let mut logger = TestLogger::new(&mut output);
+        logger.is_repl = is_repl;
+        f(&Rc::new(RefCell::new(LoggerImpl::from(logger))))
+    };
+    (result, String::from_utf8(output).expect("Not UTF-8"))
+}
+
+/// Runs `f` with a `Lox` writing to a buffer, see `with_logger`
+fn with_lox<T>(f: impl FnOnce(&mut Lox) -> T) -> (T, String) {
+    with_logger(false, |logger| f(&mut Lox::new(logger, false)))
+}
+
+fn lox_run(source: &str) -> String {
+    let (result, output) = with_lox(|lox| lox.run(source));
     assert!(result.is_ok());
-    output.clone()
+    output
+}
+
+fn lox_ast(source: &str, format: AstFormat) -> String {
+    let (ast, _) = with_lox(|lox| lox.ast(source, format).ok());
+    ast.expect("source should parse")
+}
+
+fn lox_format(source: &str) -> String {
+    let (formatted, _) = with_lox(|lox| lox.format(source).ok());
+    formatted.expect("source should parse")
 }
 
 fn assert_output(source: &str, expected: &str) {
-    let output = lox_run(source);
-    assert_eq!(
-        String::from_utf8(output).expect("Not UTF-8").trim(),
-        expected,
-    );
+    assert_eq!(lox_run(source).trim(), expected);
 }
 
 fn assert_output_list(source: &str, expected: &[&str]) {
     let output = lox_run(source);
-    let output = String::from_utf8(output).expect("Not UTF-8");
-    for (i, result) in output.split('\n').enumerate() {
-        if !result.is_empty() {
-            assert_eq!(result, expected[i]);
-        }
-    }
+    let output: Vec<&str> = output.lines().collect();
+    assert_eq!(output, expected);
 }
 
 #[test]
 fn test_shadowing() {
-    let source = r#"
+    let source = r"
         let a = 1;
         {
             let a = a + 2;
             print a; // 3
         }
-    "#;
+    ";
     assert_output(source, "3");
 }
 
@@ -77,14 +116,14 @@ fn test_block() {
             "inner a", "outer b", "global c", "outer a", "outer b", "global c", "inner d",
             "global a", "global b", "global c",
         ],
-    )
+    );
 }
 
 #[test]
 fn test_operator_precedence() {
-    let source = r#"
+    let source = r"
         print 2 + 3 * 4 * 5 - 6;
-    "#;
+    ";
     assert_output(source, "56");
 }
 
@@ -137,13 +176,13 @@ fn test_logical_operator() {
 
 #[test]
 fn test_while() {
-    let source = r#"
+    let source = r"
         let i = 0;
         while (i < 5) {
             print i;
             i = i + 1;
         }
-    "#;
+    ";
 
     assert_output_list(source, &["0", "1", "2", "3", "4"]);
 }
@@ -151,7 +190,7 @@ fn test_while() {
 #[test]
 
 fn test_for_continue_break() {
-    let source = r#"
+    let source = r"
         for (var i = 0; i <= 10; i = i + 1) {
             if i == 2 or i == 3 {
                 continue;
@@ -161,7 +200,914 @@ fn test_for_continue_break() {
                 break;
             }
         }
-    "#;
+    ";
 
     assert_output_list(source, &["0", "1", "4", "5"]);
 }
+
+#[test]
+fn test_nested_continue() {
+    let source = r#"
+        for (var i = 0; i < 3; i = i + 1) {
+            {
+                if i == 1 {
+                    {
+                        continue;
+                    }
+                }
+                print i;
+            }
+            print "after";
+        }
+    "#;
+
+    assert_output_list(source, &["0", "after", "2", "after"]);
+}
+
+#[test]
+fn test_error_line() {
+    assert_output(
+        "let a = 1;\n\nprint -\"a\";",
+        "[ln 3 col 8] RuntimeError : Operand must be a number",
+    );
+}
+
+#[test]
+fn test_unterminated_block_comment() {
+    assert_output(
+        "print 1; /* open",
+        "[ln 1 col 16] ScannerError : Unterminated block comment\n1",
+    );
+}
+
+#[test]
+fn test_nan_is_not_equal() {
+    use crate::lox::LoxValue;
+    let nan = LoxValue::Number(f64::NAN);
+    assert!(!nan.is_equal(&nan));
+    assert!(!nan.is_equal(&LoxValue::Number(1.0)));
+    assert!(LoxValue::Number(1.0).is_equal(&LoxValue::Number(1.0)));
+}
+
+#[test]
+fn test_ast_json() {
+    let ast: serde_json::Value =
+        serde_json::from_str(&lox_ast("let a = -1;", AstFormat::Json)).expect("Invalid json");
+    let token = |token_type, lexeme, column| {
+        json!({
+            "token_type": token_type,
+            "lexeme": lexeme,
+            "literal": null,
+            "position": { "line": 1, "column": column },
+        })
+    };
+    assert_eq!(
+        ast,
+        json!({
+            "version": 2,
+            "statements": [{
+                "kind": "Let",
+                "value": [
+                    token("IDENTIFIER", "a", 6),
+                    {
+                        "kind": "Unary",
+                        "value": [
+                            token("MINUS", "-", 10),
+                            { "kind": "Literal", "value": { "kind": "Number", "value": 1.0 } },
+                        ],
+                    },
+                ],
+            }],
+        })
+    );
+}
+
+#[test]
+fn test_ast_json_round_trip() {
+    let source = r#"
+        let i = 0;
+        while i < 3 {
+            if i == 1 or nil {
+                continue;
+            } else {
+                print "i = " + i;
+            }
+            i = i + 1;
+        }
+        loop { break; }
+    "#;
+    let json = lox_ast(source, AstFormat::Json);
+    let statements = ast::from_json(&json).expect("Invalid json");
+    assert_eq!(ast::to_json(&statements), json);
+    assert_eq!(
+        AstFormat::Sexpr.format(&statements),
+        lox_ast(source, AstFormat::Sexpr)
+    );
+}
+
+#[test]
+fn test_format() {
+    let source = "// header
+let a = 1;   let b=-a+2 ; // trailing
+
+
+if a<b{print \"yes\";}else if ! (a==b) {
+print clock( ) ;
+} else {}
+for (let i = 0;i<3;i=i+1) {
+  /* inner */
+  if i == 1 { continue; }
+
+}
+";
+    let expected = "// header
+let a = 1;
+let b = -a + 2; // trailing
+
+if a < b {
+    print \"yes\";
+} else if !(a == b) {
+    print clock();
+} else {}
+for (let i = 0; i < 3; i = i + 1) {
+    /* inner */
+    if i == 1 {
+        continue;
+    }
+}
+";
+    assert_eq!(lox_format(source), expected);
+    assert_eq!(lox_format(expected), expected);
+}
+
+#[test]
+fn test_format_expression_blocks() {
+    let source = "let a = if b {1} else {loop {break 2;}};\n";
+    let expected = "let a = if b {
+    1
+} else {
+    loop {
+        break 2;
+    }
+};
+";
+    assert_eq!(lox_format(source), expected);
+}
+
+#[test]
+fn test_format_maps() {
+    let source = "let m = {\"a\" :{},\n\"b\":[1]};\nfor e in m {print {1:e}[1];}\n";
+    let expected = "let m = {\"a\": {}, \"b\": [1]};
+for e in m {
+    print {1: e}[1];
+}
+";
+    assert_eq!(lox_format(source), expected);
+}
+
+#[test]
+fn test_cst_is_lossless() {
+    let source =
+        "// header\r\nlet a = 1;\t/* trailing */\n\n\nwhile a < 3 { a = a + 1; } // end\n  ";
+    let (tokens, _) = with_logger(false, |logger| {
+        Scanner::new(logger, String::from(source)).scan_lossless()
+    });
+    let program = cst::build(tokens);
+    assert_eq!(program.text(), source);
+
+    let kinds: Vec<NodeKind> = program
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node.kind),
+            SyntaxElement::Token(_) => None,
+        })
+        .collect();
+    assert_eq!(kinds, [NodeKind::Let, NodeKind::While]);
+}
+
+#[test]
+fn test_cst_break_value() {
+    let (cst, _) = with_lox(|lox| lox.cst("'outer: loop { break 'outer 1; }").ok());
+    let expected = [
+        "Program",
+        "    Loop",
+        "        LABEL \"'outer\"",
+        "        COLON \":\" trailing: [\" \"]",
+        "        LOOP \"loop\" trailing: [\" \"]",
+        "        Block",
+        "            LEFT_BRACE \"{\" trailing: [\" \"]",
+        "            Break",
+        "                BREAK \"break\" trailing: [\" \"]",
+        "                LABEL \"'outer\" trailing: [\" \"]",
+        "                Expr",
+        "                    NUMBER \"1\"",
+        "                SEMICOLON \";\" trailing: [\" \"]",
+        "            RIGHT_BRACE \"}\"",
+        "    EOF \"\"\n",
+    ];
+    assert_eq!(cst.expect("source should parse"), expected.join("\n"));
+}
+
+#[test]
+fn test_tokens() {
+    let ((text, json), _) = with_lox(|lox| {
+        (
+            lox.tokens("let a = 1;", TokenFormat::Text),
+            lox.tokens("1;", TokenFormat::Json),
+        )
+    });
+    let expected = [
+        "ln 1 col 4       LET            let",
+        "ln 1 col 6       IDENTIFIER     a",
+        "ln 1 col 8       EQUAL          =",
+        "ln 1 col 10      NUMBER         1                1",
+        "ln 1 col 11      SEMICOLON      ;",
+        "ln 1 col 11      EOF",
+    ];
+    assert_eq!(text, expected.join("\n"));
+
+    let tokens: Value = serde_json::from_str(&json).expect("Invalid json");
+    let position = |column| json!({ "line": 1, "column": column });
+    assert_eq!(
+        tokens,
+        json!([
+            {
+                "token_type": "NUMBER",
+                "lexeme": "1",
+                "literal": { "kind": "Number", "value": 1.0 },
+                "position": position(2),
+            },
+            {
+                "token_type": "SEMICOLON",
+                "lexeme": ";",
+                "literal": null,
+                "position": position(3),
+            },
+            {
+                "token_type": "EOF",
+                "lexeme": "",
+                "literal": null,
+                "position": position(3),
+            },
+        ])
+    );
+}
+
+#[test]
+fn test_repl_incomplete_input() {
+    assert!(repl::is_incomplete("while i < 3 {"));
+    assert!(repl::is_incomplete("print clock(\n"));
+    assert!(repl::is_incomplete("let xs = [\n    1,"));
+    assert!(repl::is_incomplete("print \"unterminated"));
+    assert!(repl::is_incomplete("/* open comment"));
+    assert!(!repl::is_incomplete("while i < 3 {\n i = i + 1;\n}"));
+    assert!(!repl::is_incomplete("print \"{\"; // ("));
+    assert!(!repl::is_incomplete("/* ( */ print 1;"));
+    assert!(!repl::is_incomplete("let xs = [\n    1,\n];"));
+}
+
+#[test]
+fn test_repl_expression_without_semicolon() {
+    let ((), output) = with_logger(true, |logger| {
+        let mut lox = Lox::new(logger, false);
+        lox.is_repl = true;
+        assert!(lox.run("1 + 2").is_ok());
+        assert!(lox.run("let a = 1; { a = a + 1; a * 10 }").is_ok());
+        assert!(lox.run("print a;").is_ok());
+        assert!(lox.run("a + 1 a").is_err());
+        lox.is_repl = false;
+        assert!(lox.run("a").is_err());
+    });
+    assert_eq!(
+        output.trim(),
+        "=> 3\n=> 20\n2\n[ln 1 col 8] ParserError at 'a': Expect ';' after expression\n[ln 1 col 2] ParserError at end: Expect ';' after expression",
+    );
+}
+
+#[test]
+fn test_repl_commands() {
+    use repl::Command;
+    assert!(Command::parse("print 1;").is_none());
+    assert!(matches!(Command::parse(":help"), Some(Command::Help)));
+    assert!(matches!(Command::parse(" exit "), Some(Command::Quit)));
+    assert!(matches!(
+        Command::parse(":ast 1 + 2;"),
+        Some(Command::Ast("1 + 2;"))
+    ));
+    assert!(matches!(
+        Command::parse(":load  a.lox "),
+        Some(Command::Load("a.lox"))
+    ));
+    assert!(matches!(
+        Command::parse(":nope 1"),
+        Some(Command::Unknown("nope"))
+    ));
+}
+
+#[test]
+fn test_golden_files() {
+    let files = golden::collect(&[PathBuf::from("tests/lox")]).expect("Failed to read tests/lox");
+    assert!(!files.is_empty());
+    let failures: Vec<String> = files
+        .iter()
+        .filter_map(
+            |file| match golden::run_file(file, false).expect("Failed to run file") {
+                Outcome::Pass => None,
+                Outcome::Fail(diff) => Some(format!("{}\n{}", file.display(), diff)),
+            },
+        )
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn test_golden_diff() {
+    let lines =
+        |lines: &[&str]| -> Vec<String> { lines.iter().map(|line| String::from(*line)).collect() };
+    assert_eq!(
+        golden::diff(&lines(&["1", "2", "3"]), &lines(&["1", "3", "4"])),
+        "  1\n- 2\n  3\n+ 4"
+    );
+}
+
+#[test]
+fn test_conformance() {
+    // A suite with the upstream layout, the chapters come from the directories
+    let root = std::env::temp_dir().join(format!("lox_rs_conformance_{}", std::process::id()));
+    let files = [
+        ("print/literal.lox", "print 1; // expect: 1"),
+        (
+            "if/else.lox",
+            "if (false) print 1; else print 2; // expect: 1",
+        ),
+        (
+            "for/syntax.lox",
+            "for (var i = 0; i < 1; i = i + 1) print i; // expect: 0",
+        ),
+        ("benchmark/fib.lox", "print 1;"),
+    ];
+    for (path, source) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().expect("Expected a directory")).expect("Failed to mkdir");
+        fs::write(path, source).expect("Failed to write a test");
+    }
+    let chapters = conformance::run(&root).expect("Failed to run the suite");
+    fs::remove_dir_all(&root).expect("Failed to clean up");
+    let summary: Vec<(usize, usize, usize)> = chapters
+        .iter()
+        .map(|chapter| (chapter.number, chapter.total, chapter.failures.len()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (8, 1, 0),
+            (9, 1, 1),
+            (10, 1, 0),
+            (11, 0, 0),
+            (12, 0, 0),
+            (13, 0, 0)
+        ]
+    );
+    assert_eq!(
+        chapters[1].to_string(),
+        "Chapter  9 Control Flow             0/1 passed"
+    );
+}
+
+#[test]
+fn test_exit_code() {
+    let exit_code = |is_compat: bool, source: &str| {
+        let (result, _) = with_lox(|lox| {
+            lox.set_compat(is_compat);
+            lox.run(source)
+        });
+        result.err().map_or(0, |error| error.exit_code())
+    };
+    assert_eq!(exit_code(false, "print 1;"), 0);
+    assert_eq!(exit_code(false, "print 1 +;"), 65);
+    assert_eq!(exit_code(true, "print 1 + nil;"), 70);
+}
+
+#[test]
+fn test_jlox_message() {
+    use crate::compat::jlox_message;
+    assert_eq!(jlox_message("Expected expression"), "Expect expression.");
+    assert_eq!(
+        jlox_message("Cannot have more than 255 arguments"),
+        "Can't have more than 255 arguments."
+    );
+    assert_eq!(
+        jlox_message("Undeclared variable 'a'"),
+        "Undefined variable 'a'."
+    );
+    assert_eq!(
+        jlox_message("Expected 2 arguments but got 1"),
+        "Expected 2 arguments but got 1."
+    );
+    // jlox has no such error
+    assert_eq!(jlox_message("Division by zero"), "Division by zero");
+}
+
+#[test]
+fn test_compat_golden_files() {
+    let files =
+        golden::collect(&[PathBuf::from("tests/compat")]).expect("Failed to read tests/compat");
+    assert!(!files.is_empty());
+    let failures: Vec<String> = files
+        .iter()
+        .filter_map(
+            |file| match golden::run_file(file, true).expect("Failed to run file") {
+                Outcome::Pass => None,
+                Outcome::Fail(diff) => Some(format!("{}\n{}", file.display(), diff)),
+            },
+        )
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn test_debugger() {
+    let source = r"
+let a = 1;
+let i = 0;
+while i < 2 {
+    print i;
+    i = i + 1;
+}
+print a;
+";
+    let commands = "p a\nset a = 10\nb 6\nd debug.lox:5\ncontinue\nnext\nstep\nfinish\nc\n";
+    let file = PathBuf::from("examples/debug.lox");
+    let breakpoint = Breakpoint::parse("debug.lox:5", &file).expect("Invalid breakpoint");
+    let (result, output) = with_lox(|lox| {
+        lox.interpreter.debugger = Some(Debugger::new(
+            file,
+            source,
+            vec![breakpoint],
+            Frontend::Console(Box::new(Cursor::new(commands))),
+        ));
+        lox.run(source)
+    });
+    assert!(result.is_ok());
+    assert_eq!(
+        output.trim(),
+        [
+            "Paused at examples/debug.lox:5    print i;",
+            "1",
+            "10",
+            "Breakpoint at examples/debug.lox:6",
+            "Deleted breakpoint at debug.lox:5",
+            "0",
+            "Paused at examples/debug.lox:6    i = i + 1;",
+            "Paused at examples/debug.lox:5    print i;",
+            "1",
+            "Paused at examples/debug.lox:6    i = i + 1;",
+            "Paused at examples/debug.lox:8    print a;",
+            "10",
+        ]
+        .join("\n")
+    );
+}
+
+/// A message with its header, as sent by dap and lsp clients
+fn frame(message: &Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// An output shared between the test and a dap connection
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dap() {
+    let source = "let a = 1;\nlet i = 0;\nwhile i < 2 {\n    print i;\n    i = i + 1;\n}\n";
+    let program = std::env::temp_dir().join("lox_rs_test_dap.lox");
+    fs::write(&program, source).expect("Failed to write program");
+    let requests = [
+        json!({ "command": "initialize", "arguments": {} }),
+        json!({ "command": "launch", "arguments": { "program": program } }),
+        json!({
+            "command": "setBreakpoints",
+            "arguments": { "source": { "path": program }, "breakpoints": [{ "line": 4 }] },
+        }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "a + 10" } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({
+            "command": "setBreakpoints",
+            "arguments": { "source": { "path": program }, "breakpoints": [] },
+        }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ];
+    let input: String = requests
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(seq, mut request)| {
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            frame(&request)
+        })
+        .collect();
+    let output = SharedOutput::default();
+    let transport = Transport::new(Box::new(Cursor::new(input)), Box::new(output.clone()));
+    let connection = Connection::new(transport);
+    dap::run(&Rc::new(RefCell::new(connection)), false);
+
+    let output = output.0.borrow().clone();
+    let mut reader = Transport::new(Box::new(Cursor::new(output)), Box::new(io::sink()));
+    let mut messages = Vec::new();
+    while let Some(message) = reader.read() {
+        let summary = match message["type"].as_str() {
+            Some("event") if message["event"] == "output" => {
+                format!(
+                    "output {}",
+                    message["body"]["output"].as_str().unwrap().trim()
+                )
+            }
+            Some("event") => format!("event {} {}", message["event"], message["body"]),
+            _ => format!(
+                "{} {} {}",
+                message["command"], message["success"], message["body"]
+            ),
+        };
+        messages.push(summary.replace(&program.display().to_string(), "<program>"));
+    }
+    let name = program.file_name().unwrap().to_string_lossy();
+    assert_eq!(
+        messages,
+        [
+            String::from(
+                r#""initialize" true {"supportsConfigurationDoneRequest":true,"supportsEvaluateForHovers":true,"supportsSetVariable":true}"#
+            ),
+            String::from("event \"initialized\" {}"),
+            String::from(r#""launch" true {}"#),
+            String::from(r#""setBreakpoints" true {"breakpoints":[{"line":4,"verified":true}]}"#),
+            String::from(r#""configurationDone" true {}"#),
+            String::from(
+                r#"event "stopped" {"allThreadsStopped":true,"reason":"breakpoint","threadId":1}"#
+            ),
+            format!(
+                r#""stackTrace" true {{"stackFrames":[{{"column":1,"id":0,"line":4,"name":"<script>","source":{{"name":"{name}","path":"<program>"}}}}],"totalFrames":1}}"#
+            ),
+            String::from(
+                r#""scopes" true {"scopes":[{"expensive":false,"name":"Locals","variablesReference":1},{"expensive":false,"name":"Globals","variablesReference":2}]}"#
+            ),
+            String::from(
+                r#""variables" true {"variables":[{"name":"a","value":"1","variablesReference":0},{"name":"clock","value":"<native fn>","variablesReference":0},{"name":"i","value":"0","variablesReference":0},{"name":"max","value":"<native fn>","variablesReference":0},{"name":"min","value":"<native fn>","variablesReference":0}]}"#
+            ),
+            String::from(r#""evaluate" true {"result":"11","variablesReference":0}"#),
+            String::from(r#""next" true {"allThreadsContinued":true}"#),
+            String::from("output 0"),
+            String::from(
+                r#"event "stopped" {"allThreadsStopped":true,"reason":"step","threadId":1}"#
+            ),
+            String::from(r#""setBreakpoints" true {"breakpoints":[]}"#),
+            String::from(r#""continue" true {"allThreadsContinued":true}"#),
+            String::from("output 1"),
+            String::from(r#"event "exited" {"exitCode":0}"#),
+            String::from("event \"terminated\" {}"),
+            String::from(r#""disconnect" true {}"#),
+        ]
+    );
+}
+
+/// Runs the lsp server on these requests, returns the messages it sent
+fn lsp_messages(requests: &[Value]) -> Vec<Value> {
+    let input: String = requests.iter().map(frame).collect();
+    let output = SharedOutput::default();
+    let transport = Transport::new(Box::new(Cursor::new(input)), Box::new(output.clone()));
+    lsp::Server::new(transport).run();
+
+    let output = output.0.borrow().clone();
+    let mut reader = Transport::new(Box::new(Cursor::new(output)), Box::new(io::sink()));
+    let mut messages = Vec::new();
+    while let Some(message) = reader.read() {
+        messages.push(message);
+    }
+    messages
+}
+
+#[test]
+fn test_lsp() {
+    let uri = "file:///test.lox";
+    let source = "let a = 1;\n{\n    let a = a + 1;\n    print a;\n}\nprint a\n";
+    let requests = [
+        json!({ "id": 1, "method": "initialize", "params": {} }),
+        json!({
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "text": source } },
+        }),
+        json!({
+            "id": 2,
+            "method": "textDocument/definition",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 3, "character": 10 } },
+        }),
+        json!({
+            "id": 3,
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 2, "character": 12 } },
+        }),
+        json!({
+            "id": 4,
+            "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": uri } },
+        }),
+        json!({ "id": 5, "method": "textDocument/rename", "params": {} }),
+        json!({
+            "id": 6,
+            "method": "textDocument/semanticTokens/full",
+            "params": { "textDocument": { "uri": uri } },
+        }),
+        json!({ "method": "exit" }),
+    ];
+    let messages = lsp_messages(&requests);
+    assert_eq!(messages.len(), 7);
+    assert_eq!(messages[0]["result"]["capabilities"]["hoverProvider"], true);
+    // The missing `;` of the last line is reported at the end of the file
+    assert_eq!(
+        messages[1]["params"]["diagnostics"],
+        json!([{
+            "range": { "start": { "line": 6, "character": 0 }, "end": { "line": 6, "character": 0 } },
+            "severity": 1,
+            "source": "lox",
+            "message": "Expect ';' after value",
+        }])
+    );
+    // The inner `a` shadows the outer one from the statement after its declaration
+    assert_eq!(
+        messages[2]["result"],
+        json!({
+            "uri": uri,
+            "range": { "start": { "line": 2, "character": 8 }, "end": { "line": 2, "character": 9 } },
+        })
+    );
+    // In its own initializer `a` is still the outer one
+    assert_eq!(
+        messages[3]["result"]["contents"]["value"],
+        "```lox\nlet a = 1;\n```"
+    );
+    let symbols: Vec<&Value> = messages[4]["result"]
+        .as_array()
+        .expect("Expected a list of symbols")
+        .iter()
+        .map(|symbol| &symbol["range"]["start"]["line"])
+        .collect();
+    assert_eq!(symbols, [&json!(0), &json!(2)]);
+    assert_eq!(messages[5]["error"]["code"], -32601);
+    // `let a = 1;` is a keyword, a variable, an operator and a number
+    let data = &messages[6]["result"]["data"]
+        .as_array()
+        .expect("Expected data")[..20];
+    assert_eq!(
+        data,
+        json!([0, 0, 3, 0, 0, 0, 4, 1, 2, 0, 0, 2, 1, 1, 0, 0, 2, 1, 4, 0])
+            .as_array()
+            .unwrap()
+            .as_slice()
+    );
+}
+
+#[test]
+fn test_lsp_utf16() {
+    let uri = "file:///test.lox";
+    // The emoji is one char but two UTF-16 code units
+    let source = "let s = \"\u{1f600}\"; print s;\n";
+    let messages = lsp_messages(&[
+        json!({
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "text": source } },
+        }),
+        json!({
+            "id": 1,
+            "method": "textDocument/definition",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 21 } },
+        }),
+        json!({
+            "id": 2,
+            "method": "textDocument/semanticTokens/full",
+            "params": { "textDocument": { "uri": uri } },
+        }),
+    ]);
+    assert_eq!(
+        messages[1]["result"]["range"],
+        json!({ "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } })
+    );
+    // The string starts at 8 and is 4 code units long, `print` starts at 14
+    let data = &messages[2]["result"]["data"]
+        .as_array()
+        .expect("Expected data")[15..25];
+    assert_eq!(
+        data,
+        json!([0, 2, 4, 3, 0, 0, 6, 5, 0, 0])
+            .as_array()
+            .unwrap()
+            .as_slice()
+    );
+}
+
+#[test]
+fn test_highlight() {
+    for (keyword, token_type) in &KEYWORDS {
+        assert!(
+            Highlight::of(*token_type).semantic_type()
+                == highlight::LEGEND.iter().position(|name| *name == "keyword"),
+            "{} should be highlighted as a keyword",
+            keyword
+        );
+    }
+    let path = Path::new("editors/vscode/syntaxes/lox.tmLanguage.json");
+    let grammar = fs::read_to_string(path).expect("Failed to read the grammar");
+    assert!(
+        grammar == highlight::grammar_json(),
+        "{} is out of date, run `cargo run -- grammar {}`",
+        path.display(),
+        path.display()
+    );
+}
+
+#[test]
+fn test_profiler() {
+    let source = "let i = 0;\nwhile i < 3 {\n    i = i + 1;\n}\nprint clock() > 0;\n";
+    let (profiler, _) = with_lox(|lox| {
+        lox.interpreter.profiler = Some(Profiler::new("profile.lox", source));
+        assert!(lox.run(source).is_ok());
+        lox.interpreter.profiler.take()
+    });
+    let profiler = profiler.expect("Expected a profiler");
+
+    let mut hits: Vec<(usize, usize)> = profiler
+        .lines
+        .iter()
+        .map(|(line, timing)| (*line, timing.hits))
+        .collect();
+    hits.sort_unstable();
+    assert_eq!(hits, [(1, 1), (2, 1), (3, 3), (5, 1)]);
+    assert_eq!(profiler.functions["clock"].hits, 1);
+    let folded = profiler.folded();
+    let stacks: Vec<&str> = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').expect("Expected a time").0)
+        .collect();
+    assert_eq!(
+        stacks,
+        [
+            "<script>;profile.lox:1",
+            "<script>;profile.lox:2",
+            "<script>;profile.lox:2;profile.lox:3",
+            "<script>;profile.lox:5",
+            "<script>;profile.lox:5;clock()",
+        ]
+    );
+    let report = profiler.to_string();
+    assert!(report
+        .lines()
+        .any(|line| line.ends_with("i = i + 1;") && line.contains(" 3 ")));
+}
+
+#[test]
+fn test_coverage() {
+    let source = r#"let i = 0;
+while i < 3 {
+    if i == 1 {
+        print "one";
+    } else {
+        print i;
+    }
+    i = i + 1;
+}
+if false {
+    print "never";
+}
+"#;
+    let (coverage, _) = with_lox(|lox| {
+        lox.interpreter.coverage = Some(Coverage::new(PathBuf::from("coverage.lox")));
+        assert!(lox.run(source).is_ok());
+        lox.interpreter.coverage.take()
+    });
+    let coverage = coverage.expect("Expected coverage");
+    assert_eq!(
+        coverage.to_string(),
+        [
+            "TN:",
+            "SF:coverage.lox",
+            "BRDA:3,0,0,1",
+            "BRDA:3,0,1,2",
+            "BRDA:10,1,0,0",
+            "BRDA:10,1,1,1",
+            "BRF:4",
+            "BRH:3",
+            "DA:1,1",
+            "DA:2,1",
+            "DA:3,3",
+            "DA:4,1",
+            "DA:6,2",
+            "DA:8,3",
+            "DA:10,1",
+            "DA:11,0",
+            "LF:8",
+            "LH:7",
+            "end_of_record\n",
+        ]
+        .join("\n")
+    );
+
+    // The statements of an `if` used as a value are lines, the `if` is hit with its `let`
+    let source =
+        "let a = 1;\nlet b = if a == 2 {\n    print \"two\";\n    a\n} else {\n    a + 1\n};\n";
+    let (coverage, _) = with_lox(|lox| {
+        lox.interpreter.coverage = Some(Coverage::new(PathBuf::from("coverage.lox")));
+        assert!(lox.run(source).is_ok());
+        lox.interpreter.coverage.take()
+    });
+    let coverage = coverage.expect("Expected coverage").to_string();
+    let lines: Vec<&str> = coverage
+        .lines()
+        .filter(|line| {
+            line.starts_with("DA:") || line.starts_with("LF:") || line.starts_with("LH:")
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        ["DA:1,1", "DA:2,1", "DA:3,0", "DA:4,0", "DA:6,1", "LF:5", "LH:3"]
+    );
+}
+
+#[test]
+fn test_tracer() {
+    let source = "let a = 1;\nif a == 1 { print a + 2; }\nlet b = a;\na = b * 2;\n";
+    let trace = |tracer: Tracer| {
+        let (result, output) = with_lox(|lox| {
+            lox.interpreter.tracer = Some(tracer);
+            lox.run(source)
+        });
+        assert!(result.is_ok());
+        output
+    };
+    assert_eq!(
+        trace(Tracer {
+            lines: Tracer::parse_lines("2"),
+            ..Tracer::default()
+        }),
+        [
+            "TRACE [ln 2 col 3] exec (if (a == 1))",
+            "TRACE [ln 2 col 5] read a = 1",
+            "TRACE [ln 2 col 5] eval (a == 1) = true",
+            "TRACE [ln 2 col 17] exec (print (a + 2))",
+            "TRACE [ln 2 col 19] read a = 1",
+            "TRACE [ln 2 col 19] eval (a + 2) = 3",
+            "3\n",
+        ]
+        .join("\n")
+    );
+    assert_eq!(
+        trace(Tracer {
+            variables: vec![String::from("b")],
+            ..Tracer::default()
+        }),
+        [
+            "3",
+            "TRACE [ln 3 col 6] write b = 1",
+            "TRACE [ln 4 col 6] read b = 1\n",
+        ]
+        .join("\n")
+    );
+    let (result, output) = with_lox(|lox| {
+        lox.interpreter.tracer = Some(Tracer {
+            variables: vec![String::from("a"), String::from("m")],
+            ..Tracer::default()
+        });
+        lox.run("let a = [[1]];\nlet m = {\"k\": 1};\na[0][0] = 2;\nm[\"k\"] = 3;\n")
+    });
+    assert!(result.is_ok());
+    assert_eq!(
+        output,
+        [
+            "TRACE [ln 1 col 6] write a = [[1]]",
+            "TRACE [ln 2 col 6] write m = {\"k\": 1}",
+            "TRACE [ln 3 col 2] read a = [[1]]",
+            "TRACE [ln 3 col 6] write a[0][0] = 2",
+            "TRACE [ln 4 col 2] read m = {\"k\": 1}",
+            "TRACE [ln 4 col 3] write m[\"k\"] = 3\n",
+        ]
+        .join("\n")
+    );
+    assert_eq!(Tracer::parse_lines("3-10"), Some(3..=10));
+    assert_eq!(Tracer::parse_lines("3-"), None);
+}
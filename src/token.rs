@@ -1,15 +1,24 @@
 use derive_new::new;
-use std::fmt::{Debug, Display, Formatter, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    str::FromStr,
+};
 
 #[rustfmt::skip]
-#[allow(non_camel_case_types, clippy::module_name_repetitions)]
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(
+    non_camel_case_types,
+    clippy::module_name_repetitions,
+    clippy::upper_case_acronyms
+)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum TokenType {
     // Single-character tokens
-    LEFT_PAREN, RIGHT_PAREN, LEFT_BRACE, RIGHT_BRACE, 
-    COMMA, DOT, MINUS, PLUS, SLASH, STAR, SEMICOLON,
+    LEFT_PAREN, RIGHT_PAREN, LEFT_BRACE, RIGHT_BRACE, LEFT_BRACKET, RIGHT_BRACKET,
+    COMMA, DOT, MINUS, PLUS, SLASH, STAR, SEMICOLON, COLON,
 
-    // One or two char tokens
+    // One, two or three char tokens
+    DOT_DOT, DOT_DOT_EQUAL,
     BANG, BANG_EQUAL,
     EQUAL, EQUAL_EQUAL,
     GREATER, GREATER_EQUAL,                          
@@ -17,16 +26,51 @@ pub enum TokenType {
 
     // Literals
     IDENTIFIER, STRING, NUMBER,
+    // 'outer, the name of a loop
+    LABEL,
 
     //Keywords
     AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR,
     PRINT, RETURN, SUPER, THIS, TRUE, LET, WHILE,
-    LOOP, BREAK, CONTINUE,
+    LOOP, BREAK, CONTINUE, IN,
 
     EOF
 }
 
-#[derive(new, Clone)]
+pub const KEYWORDS: [(&str, TokenType); 21] = [
+    ("and", TokenType::AND),
+    ("class", TokenType::CLASS),
+    ("else", TokenType::ELSE),
+    ("false", TokenType::FALSE),
+    ("for", TokenType::FOR),
+    ("fun", TokenType::FUN),
+    ("if", TokenType::IF),
+    ("nil", TokenType::NIL),
+    ("or", TokenType::OR),
+    ("print", TokenType::PRINT),
+    ("return", TokenType::RETURN),
+    ("super", TokenType::SUPER),
+    ("this", TokenType::THIS),
+    ("true", TokenType::TRUE),
+    ("let", TokenType::LET),
+    ("var", TokenType::LET),
+    ("while", TokenType::WHILE),
+    ("loop", TokenType::LOOP),
+    ("break", TokenType::BREAK),
+    ("continue", TokenType::CONTINUE),
+    ("in", TokenType::IN),
+];
+
+pub fn keyword(identifier: &str) -> Option<TokenType> {
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == identifier)
+        .map(|(_, token_type)| *token_type)
+}
+
+// `token_type` is part of the json of the tokens and the ast
+#[allow(clippy::struct_field_names)]
+#[derive(new, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
@@ -49,7 +93,7 @@ impl Debug for Token {
     }
 }
 
-#[derive(new, Clone, Copy, Debug)]
+#[derive(new, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -72,7 +116,9 @@ impl Display for Position {
     }
 }
 
-#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
 pub enum Literal {
     String(String),
     Number(f64),
@@ -84,11 +130,60 @@ pub enum Literal {
 impl Display for Literal {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
-            Literal::Number(value) => write!(f, "{}", value),
-            Literal::String(value) => write!(f, "\"{}\"", value),
+            Literal::Number(value) => write!(f, "{value}"),
+            Literal::String(value) => write!(f, "\"{value}\""),
             Literal::FALSE => write!(f, "false"),
             Literal::TRUE => write!(f, "true"),
             Literal::Nil => write!(f, "nil"),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum TokenFormat {
+    Text,
+    Json,
+}
+
+impl TokenFormat {
+    pub fn format(self, tokens: &[Token]) -> String {
+        match self {
+            TokenFormat::Text => {
+                let lines: Vec<String> = tokens
+                    .iter()
+                    .map(|token| {
+                        let literal = match &token.literal {
+                            Some(literal) => literal.to_string(),
+                            None => String::new(),
+                        };
+                        format!(
+                            "{:<16} {:<14} {:<16} {}",
+                            token.position.to_string(),
+                            format!("{:?}", token.token_type),
+                            token.lexeme,
+                            literal
+                        )
+                        .trim_end()
+                        .to_string()
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+            TokenFormat::Json => {
+                serde_json::to_string_pretty(tokens).expect("tokens should always be serializable")
+            }
+        }
+    }
+}
+
+impl FromStr for TokenFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TokenFormat::Text),
+            "json" => Ok(TokenFormat::Json),
+            _ => Err(format!("Unknown token format \"{s}\"")),
+        }
+    }
+}
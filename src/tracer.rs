@@ -0,0 +1,125 @@
+use crate::{
+    ast::{Expr, Stmt},
+    lox::LoxValue,
+    token::{Position, Token},
+};
+use std::ops::RangeInclusive;
+
+// Describes what the interpreter does, one line per event:
+//
+//     TRACE [ln 2 col 3] exec (if (a == 1))
+//     TRACE [ln 2 col 5] read a = 1
+//     TRACE [ln 2 col 7] eval (a == 1) = true
+//
+// Literals are left out, their value is in their source.
+
+#[derive(Default)]
+pub struct Tracer {
+    /// Only the events on these lines are traced
+    pub lines: Option<RangeInclusive<usize>>,
+    /// When not empty only the reads and writes of these variables are traced
+    pub variables: Vec<String>,
+}
+
+impl Tracer {
+    /// Parses `3-10`, or only `3` for a single line
+    pub fn parse_lines(text: &str) -> Option<RangeInclusive<usize>> {
+        let (start, end) = text.split_once('-').unwrap_or((text, text));
+        Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+    }
+
+    fn is_traced_line(&self, position: Position) -> bool {
+        self.lines
+            .as_ref()
+            .is_none_or(|lines| lines.contains(&position.line))
+    }
+
+    fn event(&self, position: Position, event: &str) -> Option<String> {
+        if self.is_traced_line(position) {
+            Some(format!("TRACE [{position}] {event}"))
+        } else {
+            None
+        }
+    }
+
+    fn variable_event(&self, token: &Token, kind: &str, value: &LoxValue) -> Option<String> {
+        if !self.variables.is_empty() && !self.variables.contains(&token.lexeme) {
+            return None;
+        }
+        self.event(
+            token.position,
+            &format!("{} {} = {}", kind, token.lexeme, value),
+        )
+    }
+
+    /// A statement about to run, blocks are left out
+    pub fn statement(&self, stmt: &Stmt) -> Option<String> {
+        if !self.variables.is_empty() {
+            return None;
+        }
+        // Only the header of the statements with a body, the body is traced when it runs
+        let description = match stmt {
+            Stmt::Block(_) => return None,
+            Stmt::If(_, condition, ..) => format!("(if {condition})"),
+            Stmt::While(_, _, condition, ..) => format!("(while {condition})"),
+            Stmt::ForIn(_, _, variable, iterable, _) => {
+                format!("(for {variable} in {iterable})")
+            }
+            _ => stmt.to_string(),
+        };
+        self.event(stmt.position()?, &format!("exec {description}"))
+    }
+
+    /// The value of an expression, variables are traced as reads and assignments as writes
+    pub fn expression(&self, expr: &Expr, value: &LoxValue) -> Option<String> {
+        match expr {
+            // The statements in an expression are traced when they run, an index assignment
+            // is traced as a write by `index_write`
+            Expr::Literal(_) | Expr::Statement(_) | Expr::SetIndex(..) => None,
+            Expr::Variable(token) => self.variable_event(token, "read", value),
+            Expr::Assign(token, _) => self.variable_event(token, "write", value),
+            _ if self.variables.is_empty() => {
+                self.event(expr.position()?, &format!("eval {expr} = {value}"))
+            }
+            _ => None,
+        }
+    }
+
+    /// A `let` declaring a variable is a write
+    pub fn declaration(&self, name: &Token, value: &LoxValue) -> Option<String> {
+        self.variable_event(name, "write", value)
+    }
+
+    /// `a[i] = v` is a write of the variable `a` holding the list or map
+    pub fn index_write(
+        &self,
+        object: &Expr,
+        bracket: &Token,
+        index: &LoxValue,
+        value: &LoxValue,
+    ) -> Option<String> {
+        if !self.variables.is_empty() {
+            let name = root_variable(object)?;
+            if !self.variables.contains(&name.lexeme) {
+                return None;
+            }
+        }
+        let index = match index {
+            LoxValue::String(index) => format!("\"{index}\""),
+            _ => index.to_string(),
+        };
+        self.event(
+            bracket.position,
+            &format!("write {object}[{index}] = {value}"),
+        )
+    }
+}
+
+/// The variable `a` of `a[0][1]` or `a.b[0]`
+fn root_variable(expr: &Expr) -> Option<&Token> {
+    match expr {
+        Expr::Variable(name) => Some(name),
+        Expr::Grouping(expr) | Expr::Index(expr, ..) | Expr::Get(expr, _) => root_variable(expr),
+        _ => None,
+    }
+}
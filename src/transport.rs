@@ -0,0 +1,49 @@
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+// The debug adapter protocol and the language server protocol both send json messages
+// over stdio, each one preceded by a `Content-Length` header and an empty line.
+
+pub struct Transport {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Transport {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Transport { input, output }
+    }
+
+    /// Reads the next message, `None` at the end of the input or if the message is malformed
+    pub fn read(&mut self) -> Option<Value> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.input.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().ok();
+            }
+        }
+        let mut body = vec![0; length?];
+        self.input.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    pub fn write(&mut self, message: &Value) {
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .and_then(|()| self.output.flush())
+        .expect("Failed to write");
+    }
+}